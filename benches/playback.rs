@@ -0,0 +1,106 @@
+//! Throughput of playback and sample processing.
+//!
+//! ```text
+//! cargo bench
+//! ```
+//!
+//! Playback is measured through libao's null driver, so it reflects the cost of this crate and
+//! libao rather than any hardware. Run without the `mock` feature, which keeps everything played
+//! in memory. Pass a name to run only the benchmarks containing it.
+
+extern crate ao;
+
+use ao::{AO, Device, Sample, SampleFormat};
+use ao::Endianness::Native;
+use ao::multi::MultiDevice;
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Frames in each buffer played, a typical period for interactive audio.
+const FRAMES: usize = 4096;
+
+/// Minimum time spent measuring each benchmark.
+const MEASURE: Duration = Duration::from_secs(1);
+
+/// Time `f`, printing the time per call and the rate at which it processes `samples`.
+fn bench<F: FnMut()>(filter: &Option<String>, name: &str, samples: usize, mut f: F) {
+    if filter.as_ref().is_some_and(|f| !name.contains(&f[..])) {
+        return;
+    }
+
+    // Warm up, and find roughly how many calls fill the measurement time.
+    let start = Instant::now();
+    let mut calls = 0u32;
+    while start.elapsed() < MEASURE / 10 {
+        f();
+        calls += 1;
+    }
+    let batch = calls.max(1);
+
+    let mut iterations = 0u64;
+    let start = Instant::now();
+    while start.elapsed() < MEASURE {
+        for _ in 0..batch {
+            f();
+        }
+        iterations += batch as u64;
+    }
+    let per_call = start.elapsed() / iterations as u32;
+    let rate = samples as f64 / per_call.as_secs_f64() / 1e6;
+    println!("{:<24} {:>10.2?}/iter {:>10.1} Msamples/s", name, per_call, rate);
+}
+
+fn stereo_tone() -> Vec<i16> {
+    (0..FRAMES * 2).map(|i| ((i as f32 * 0.01).sin() * 16000.0) as i16).collect()
+}
+
+fn open<'a, S: Sample>(lib: &'a AO, channels: usize) -> Device<'a, S> {
+    let driver = lib.get_driver("null").expect("No null driver available");
+    let format = SampleFormat::<S, &str>::new(44100, channels, Native, None);
+    driver.open_live(&format).expect("Failed to open null device")
+}
+
+fn main() {
+    // Cargo passes `--bench`; anything else names the benchmarks to run.
+    let filter = env::args().skip(1).find(|a| !a.starts_with("--"));
+    let lib = AO::init();
+    let samples = stereo_tone();
+
+    let mut device = open::<i16>(&lib, 2);
+    bench(&filter, "play", samples.len(), || device.play(&samples).unwrap());
+    device.set_volume(0.5);
+    bench(&filter, "play_volume", samples.len(), || device.play(&samples).unwrap());
+    device.set_dither(true);
+    bench(&filter, "play_volume_dithered", samples.len(), || device.play(&samples).unwrap());
+    drop(device);
+
+    let frames: Vec<[i16; 2]> = samples.chunks(2).map(|f| [f[0], f[1]]).collect();
+    let device = open::<[i16; 2]>(&lib, 2);
+    bench(&filter, "play_frames", samples.len(), || device.play(&frames).unwrap());
+    drop(device);
+
+    let mut scaled = Vec::with_capacity(samples.len());
+    bench(&filter, "scale_i16", samples.len(), || {
+        scaled.clear();
+        scaled.extend(black_box(&samples).iter().map(|s| s.scale(0.5)));
+        black_box(&scaled);
+    });
+
+    let mut noise = 0u32;
+    bench(&filter, "scale_dithered_i16", samples.len(), || {
+        scaled.clear();
+        scaled.extend(black_box(&samples).iter().map(|s| s.scale_dithered(0.5, &mut || {
+            noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            noise as f32 / u32::MAX as f32 - 0.5
+        })));
+        black_box(&scaled);
+    });
+
+    // Route four channels to two stereo devices.
+    let quad: Vec<i16> = samples.iter().chain(samples.iter()).cloned().collect();
+    let mut multi = MultiDevice::new(4);
+    multi.add(open::<i16>(&lib, 2), &[0, 1]).unwrap();
+    multi.add(open::<i16>(&lib, 2), &[2, 3]).unwrap();
+    bench(&filter, "multi_split", quad.len(), || multi.play(&quad).unwrap());
+}
@@ -0,0 +1,48 @@
+//! List the drivers libao has loaded.
+//!
+//! ```text
+//! cargo run --example ao-devices
+//! ```
+//!
+//! The default driver is marked with `*`.
+
+extern crate ao;
+
+use ao::{AO, DriverType, Endianness};
+
+fn main() {
+    let lib = AO::init();
+    let default = lib.get_driver("")
+                     .and_then(|d| d.get_info())
+                     .map(|i| i.short_name);
+
+    for driver in lib.drivers() {
+        let info = match driver.get_info() {
+            Some(info) => info,
+            None => continue,
+        };
+        let marker = if Some(info.short_name) == default { "*" } else { " " };
+        println!("{} {:<10} {}", marker, info.short_name, info.name);
+
+        let flavor = match info.flavor {
+            DriverType::Live => "live",
+            DriverType::File => "file",
+            _ => "unknown",
+        };
+        let byte_order = match driver.capabilities().preferred_byte_order {
+            Some(Endianness::Little) => "little-endian",
+            Some(Endianness::Big) => "big-endian",
+            Some(Endianness::Native) => "native",
+            None => "unknown",
+        };
+        println!("    type: {}, priority: {}, byte order: {}", flavor, info.priority, byte_order);
+
+        let options: Vec<&str> = info.options().collect();
+        if !options.is_empty() {
+            println!("    options: {}", options.join(", "));
+        }
+        if let Some(comment) = info.comment {
+            println!("    {}", comment);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Play a WAV file.
+//!
+//! ```text
+//! cargo run --example ao-play -- [--driver NAME] FILE.wav
+//! ```
+//!
+//! Uncompressed PCM files of 8, 16, 24 or 32 bits are supported. The output is opened in whatever
+//! format the file has by an `AutoFormatDevice`.
+
+extern crate ao;
+
+use ao::{AO, Endianness};
+use ao::auto::{AutoFormatDevice, SampleBuffer};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::mem;
+use std::process;
+use std::slice;
+
+const USAGE: &str = "Usage: ao-play [--driver NAME] FILE";
+
+/// Format of the samples in a WAV file.
+struct WavFormat {
+    channels: usize,
+    sample_rate: usize,
+    bits: usize,
+}
+
+/// Samples decoded from a WAV file, ready to play.
+struct Buffer {
+    channels: usize,
+    sample_rate: usize,
+    bits: usize,
+    /// Little-endian sample bytes, stored as words so they are aligned for any sample type.
+    words: Vec<u32>,
+    len: usize,
+}
+
+impl SampleBuffer for Buffer {
+    fn channels(&self) -> usize { self.channels }
+    fn sample_rate(&self) -> usize { self.sample_rate }
+    fn endianness(&self) -> Endianness { Endianness::Little }
+    fn sample_width(&self) -> usize { self.bits }
+    fn data<'a>(&self) -> &'a [u8] {
+        // The buffer outlives every call to `play` it is passed to.
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl Buffer {
+    /// Replace the contents with samples read from the file.
+    ///
+    /// libao has no unsigned or 24-bit formats, so 8-bit samples are made signed and 24-bit
+    /// samples widened to 32 bits.
+    fn fill(&mut self, format: &WavFormat, bytes: &[u8]) {
+        let mut out = Vec::with_capacity(bytes.len() * 4 / 3 + 4);
+        match format.bits {
+            8 => out.extend(bytes.iter().map(|&b| b ^ 0x80)),
+            24 => {
+                for s in bytes.chunks(3).filter(|s| s.len() == 3) {
+                    out.extend_from_slice(&[0, s[0], s[1], s[2]]);
+                }
+            }
+            _ => out.extend_from_slice(bytes),
+        }
+        self.bits = if format.bits == 24 { 32 } else { format.bits };
+        self.channels = format.channels;
+        self.sample_rate = format.sample_rate;
+        self.len = out.len();
+        self.words.clear();
+        self.words.resize(out.len().div_ceil(mem::size_of::<u32>()), 0);
+        unsafe {
+            slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, out.len())
+                .copy_from_slice(&out);
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Read the headers of a WAV file, leaving `input` at the start of the sample data.
+///
+/// Returns the format and the length of the sample data in bytes.
+fn read_header<R: Read>(input: &mut R) -> io::Result<(WavFormat, u64)> {
+    let mut riff = [0; 12];
+    input.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let mut format = None;
+    loop {
+        let mut header = [0; 8];
+        input.read_exact(&mut header)?;
+        let len = read_u32(&header[4..]) as u64;
+        match &header[0..4] {
+            b"fmt " => {
+                let mut fmt = vec![0; len as usize];
+                input.read_exact(&mut fmt)?;
+                if fmt.len() < 16 {
+                    return Err(invalid("truncated format chunk"));
+                }
+                // WAVE_FORMAT_EXTENSIBLE names the real format in the first bytes of its GUID.
+                let tag = match read_u16(&fmt) {
+                    0xfffe if fmt.len() >= 26 => read_u16(&fmt[24..]),
+                    tag => tag,
+                };
+                if tag != 1 {
+                    return Err(invalid("only uncompressed PCM is supported"));
+                }
+                let bits = read_u16(&fmt[14..]) as usize;
+                if ![8, 16, 24, 32].contains(&bits) {
+                    return Err(invalid("unsupported sample width"));
+                }
+                format = Some(WavFormat {
+                    channels: read_u16(&fmt[2..]) as usize,
+                    sample_rate: read_u32(&fmt[4..]) as usize,
+                    bits,
+                });
+            }
+            b"data" => {
+                return match format {
+                    Some(format) => Ok((format, len)),
+                    None => Err(invalid("sample data precedes format")),
+                };
+            }
+            _ => {
+                io::copy(&mut input.by_ref().take(len), &mut io::sink())?;
+            }
+        }
+        // Chunks are padded to an even length.
+        if len % 2 == 1 {
+            input.read_exact(&mut [0])?;
+        }
+    }
+}
+
+fn play(driver_name: &str, path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut input = BufReader::new(file);
+    let (format, len) = read_header(&mut input).map_err(|e| format!("{}: {}", path, e))?;
+    if format.channels == 0 {
+        return Err(format!("{}: no channels", path));
+    }
+
+    let lib = AO::init();
+    let driver = lib.get_driver(driver_name)
+                    .ok_or_else(|| format!("no driver named {:?}", driver_name))?;
+    let mut device = AutoFormatDevice::new(driver, Vec::<&str>::new());
+
+    // Play a tenth of a second at a time.
+    let frame = format.channels * format.bits / 8;
+    let mut chunk = vec![0; (format.sample_rate / 10).max(1) * frame];
+    let mut data = input.take(len);
+    let mut buffer = Buffer {
+        channels: 0,
+        sample_rate: 0,
+        bits: 0,
+        words: Vec::new(),
+        len: 0,
+    };
+    loop {
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match data.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(format!("{}: {}", path, e)),
+            }
+        }
+        // Drop any partial frame at the end of a truncated file.
+        let whole = filled - filled % frame;
+        if whole == 0 {
+            return Ok(());
+        }
+        buffer.fill(&format, &chunk[..whole]);
+        device.play(&buffer).map_err(|e| format!("playback failed: {}", e))?;
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut driver = String::new();
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--driver" => driver = args.next().unwrap_or_else(|| usage()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    if let Err(e) = play(&driver, &path) {
+        eprintln!("ao-play: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,98 @@
+//! Report which formats a live driver can open.
+//!
+//! ```text
+//! cargo run --example ao-probe -- [--driver NAME] [--rates 22050,44100,48000]
+//! ```
+//!
+//! For each sample rate, prints what `Driver::probe_capabilities` found, then tries every
+//! combination of sample width and channel count, which is useful to include in bug reports.
+
+extern crate ao;
+
+use ao::{AO, AoResult, Driver, DriverType, Sample, SampleFormat};
+use ao::Endianness::Native;
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage: ao-probe [--driver NAME] [--rates HZ,HZ,...]";
+
+const DEFAULT_RATES: &[usize] = &[8000, 11025, 16000, 22050, 32000, 44100, 48000, 96000];
+const CHANNELS: &[usize] = &[1, 2, 4, 6, 8];
+
+fn open<S: Sample>(driver: &Driver, rate: usize, channels: usize) -> AoResult<()> {
+    let format = SampleFormat::<S, &str>::new(rate, channels, Native, None);
+    driver.open_live(&format).map(|_| ())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut name = String::new();
+    let mut rates = DEFAULT_RATES.to_vec();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--driver" => name = args.next().unwrap_or_else(|| usage()),
+            "--rates" => {
+                let list = args.next().unwrap_or_else(|| usage());
+                rates = list.split(',')
+                            .map(|r| r.trim().parse().unwrap_or_else(|_| usage()))
+                            .collect();
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => usage(),
+        }
+    }
+
+    let lib = AO::init();
+    let driver = match lib.get_driver(&name[..]) {
+        Some(d) => d,
+        None => {
+            eprintln!("ao-probe: no driver named {:?}", name);
+            process::exit(1);
+        }
+    };
+    let info = driver.get_info().expect("Driver has no information");
+    if info.flavor != DriverType::Live {
+        eprintln!("ao-probe: {} is not a live driver", info.short_name);
+        process::exit(1);
+    }
+    println!("driver: {} ({})", info.short_name, info.name);
+
+    for &rate in rates.iter() {
+        let caps = driver.probe_capabilities(rate);
+        let channels = match caps.max_channels {
+            Some(max) => format!("up to {}", max),
+            None => "8 or more".to_string(),
+        };
+        println!("\n{} Hz: bits {:?}, channels {}", rate, caps.bits, channels);
+
+        print!("{:>8}", "");
+        for channels in CHANNELS {
+            print!("{:>12}", format!("{} ch", channels));
+        }
+        println!();
+        for &bits in [8, 16, 32].iter() {
+            print!("{:>8}", format!("{}-bit", bits));
+            for &channels in CHANNELS {
+                let result = match bits {
+                    8 => open::<i8>(&driver, rate, channels),
+                    16 => open::<i16>(&driver, rate, channels),
+                    _ => open::<i32>(&driver, rate, channels),
+                };
+                let cell = match result {
+                    Ok(()) => "ok".to_string(),
+                    Err(e) => format!("{:?}", e),
+                };
+                print!("{:>12}", cell);
+            }
+            println!();
+        }
+    }
+}
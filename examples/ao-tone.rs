@@ -0,0 +1,171 @@
+//! Play a test tone.
+//!
+//! ```text
+//! cargo run --example ao-tone -- --frequency 440 --duration 2 --waveform square
+//! ```
+//!
+//! Run with `--help` for every option. File drivers such as `wav` need `--output`.
+
+extern crate ao;
+
+use ao::{AO, DriverType, Sample, SampleFormat};
+use ao::Endianness::Native;
+use std::env;
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+Usage: ao-tone [options]
+
+Options:
+    --driver NAME       libao driver to use (default: libao's default)
+    --output PATH       file to write, for file drivers
+    --rate HZ           sample rate (default: 44100)
+    --bits N            bits per sample: 8, 16 or 32 (default: 16)
+    --channels N        number of channels (default: 2)
+    --waveform SHAPE    sine, square, sawtooth or triangle (default: sine)
+    --frequency HZ      tone frequency (default: 440)
+    --duration SECONDS  length of the tone (default: 1)
+    --amplitude LEVEL   peak level, from 0 to 1 (default: 0.5)";
+
+#[derive(Clone, Copy)]
+enum Waveform {
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+}
+
+impl Waveform {
+    /// Value at `phase`, in cycles, ranging from -1 to 1.
+    fn at(self, phase: f64) -> f64 {
+        let phase = phase.fract();
+        match self {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+struct Options {
+    driver: String,
+    output: Option<PathBuf>,
+    rate: usize,
+    bits: usize,
+    channels: usize,
+    waveform: Waveform,
+    frequency: f64,
+    duration: f64,
+    amplitude: f64,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("ao-tone: {}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    match value.as_ref().map(|v| v.parse()) {
+        Some(Ok(v)) => v,
+        Some(Err(_)) => fail(&format!("invalid value for {}", flag)),
+        None => fail(&format!("{} needs a value", flag)),
+    }
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        driver: String::new(),
+        output: None,
+        rate: 44100,
+        bits: 16,
+        channels: 2,
+        waveform: Waveform::Sine,
+        frequency: 440.0,
+        duration: 1.0,
+        amplitude: 0.5,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        match &flag[..] {
+            "--driver" => options.driver = parse(&flag, args.next()),
+            "--output" => options.output = Some(parse::<String>(&flag, args.next()).into()),
+            "--rate" => options.rate = parse(&flag, args.next()),
+            "--bits" => options.bits = parse(&flag, args.next()),
+            "--channels" => options.channels = parse(&flag, args.next()),
+            "--waveform" => {
+                options.waveform = match &parse::<String>(&flag, args.next())[..] {
+                    "sine" => Waveform::Sine,
+                    "square" => Waveform::Square,
+                    "sawtooth" => Waveform::Sawtooth,
+                    "triangle" => Waveform::Triangle,
+                    _ => fail("unknown waveform"),
+                }
+            }
+            "--frequency" => options.frequency = parse(&flag, args.next()),
+            "--duration" => options.duration = parse(&flag, args.next()),
+            "--amplitude" => options.amplitude = parse(&flag, args.next()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => fail(&format!("unknown option {}", flag)),
+        }
+    }
+
+    if options.rate == 0 || options.channels == 0 {
+        fail("sample rate and channels must be positive");
+    }
+    if !(0.0..=1.0).contains(&options.amplitude) {
+        fail("amplitude must be between 0 and 1");
+    }
+    options
+}
+
+/// Generate the tone and play it, converting each value to a sample with `convert`.
+fn play<S: Sample, F: Fn(f64) -> S>(lib: &AO, options: &Options, convert: F) -> Result<(), String> {
+    let driver = lib.get_driver(&options.driver[..])
+                    .ok_or_else(|| format!("no driver named {:?}", options.driver))?;
+    let format = SampleFormat::<S, &str>::new(options.rate, options.channels, Native, None);
+    let is_file = driver.get_info().is_some_and(|i| i.flavor == DriverType::File);
+    let device = match (is_file, &options.output) {
+        (true, Some(path)) => driver.open_file(&format, path, true),
+        (true, None) => return Err("file drivers need --output".to_string()),
+        (false, _) => driver.open_live(&format),
+    };
+    let device = device.map_err(|e| format!("failed to open device: {}", e))?;
+
+    let frames = (options.duration * options.rate as f64) as usize;
+    let step = options.frequency / options.rate as f64;
+    // Play a tenth of a second at a time.
+    let chunk = (options.rate / 10).max(1);
+    let mut samples = Vec::with_capacity(chunk * options.channels);
+    for start in (0..frames).step_by(chunk) {
+        samples.clear();
+        for frame in start..frames.min(start + chunk) {
+            let value = convert(options.amplitude * options.waveform.at(frame as f64 * step));
+            samples.extend((0..options.channels).map(|_| value));
+        }
+        device.play(&samples).map_err(|e| format!("playback failed: {}", e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = parse_args();
+    let lib = AO::init();
+    let result = match options.bits {
+        8 => play(&lib, &options, |x| (x * i8::MAX as f64) as i8),
+        16 => play(&lib, &options, |x| (x * i16::MAX as f64) as i16),
+        32 => play(&lib, &options, |x| (x * i32::MAX as f64) as i32),
+        _ => fail("bits must be 8, 16 or 32"),
+    };
+    if let Err(e) = result {
+        eprintln!("ao-tone: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,82 @@
+//! Play a sequence of buffers in arbitrary formats through an `AutoFormatDevice`.
+//!
+//! Input is a line of channel matrices separated by `;`, followed by buffers, each an eight byte
+//! header (sample width, channels, endianness, sample rate, data length and the data's offset from
+//! an aligned address) and its data. Widths and offsets are used as given, so unsupported widths
+//! and misaligned buffers are exercised along with valid ones.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use ao::auto::{AutoFormatDevice, SampleBuffer};
+use ao::{mock, Endianness, AO};
+use std::sync::OnceLock;
+
+static LIB: OnceLock<AO> = OnceLock::new();
+
+struct Buffer {
+    width: usize,
+    channels: usize,
+    rate: usize,
+    endianness: Endianness,
+    /// Sample bytes, stored as words so they start aligned for any sample type.
+    words: Vec<u32>,
+    /// Offset of the data from the start of `words`.
+    offset: usize,
+    len: usize,
+}
+
+impl SampleBuffer for Buffer {
+    fn channels(&self) -> usize { self.channels }
+    fn sample_rate(&self) -> usize { self.rate }
+    fn endianness(&self) -> Endianness { self.endianness }
+    fn sample_width(&self) -> usize { self.width }
+    fn data<'a>(&self) -> &'a [u8] {
+        unsafe {
+            std::slice::from_raw_parts((self.words.as_ptr() as *const u8).add(self.offset), self.len)
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let split = data.iter().position(|&b| b == b'\n').unwrap_or(data.len());
+    let matrices: Vec<&str> = match std::str::from_utf8(&data[..split]) {
+        Ok(line) if !line.is_empty() => line.split(';').collect(),
+        _ => Vec::new(),
+    };
+    let mut rest = &data[(split + 1).min(data.len())..];
+
+    let lib = LIB.get_or_init(AO::init);
+    let driver = lib.get_driver("null").expect("Mock has no null driver");
+    let mut device = AutoFormatDevice::new(driver, matrices);
+
+    while rest.len() >= 8 {
+        let (header, tail) = rest.split_at(8);
+        let len = (u16::from_le_bytes([header[5], header[6]]) as usize).min(tail.len());
+        let (bytes, tail) = tail.split_at(len);
+        rest = tail;
+
+        let offset = header[7] as usize % 4;
+        let mut words = vec![0u32; (offset + len + 3) / 4];
+        unsafe {
+            std::slice::from_raw_parts_mut((words.as_mut_ptr() as *mut u8).add(offset), len)
+                .copy_from_slice(bytes);
+        }
+        let buffer = Buffer {
+            width: header[0] as usize,
+            channels: header[1] as usize % 9,
+            endianness: match header[2] % 3 {
+                0 => Endianness::Native,
+                1 => Endianness::Little,
+                _ => Endianness::Big,
+            },
+            rate: u16::from_le_bytes([header[3], header[4]]) as usize,
+            words,
+            offset,
+            len,
+        };
+        let _ = device.play(&buffer);
+    }
+    drop(device);
+    mock::clear();
+});
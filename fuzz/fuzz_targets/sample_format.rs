@@ -0,0 +1,49 @@
+//! Open devices in arbitrary formats, checking the format reaches libao intact.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use ao::{mock, Endianness, Sample, SampleFormat, AO};
+use std::sync::OnceLock;
+
+static LIB: OnceLock<AO> = OnceLock::new();
+
+fn open<S: Sample>(rate: usize, channels: usize, byte_order: Endianness, matrix: Option<&str>) {
+    let lib = LIB.get_or_init(AO::init);
+    let driver = lib.get_driver("null").expect("Mock has no null driver");
+    let format = SampleFormat::<S, &str>::new(rate, channels, byte_order, matrix);
+    if let Ok(device) = driver.open_live(&format) {
+        let record = mock::devices().pop().expect("Open device was not recorded");
+        assert_eq!(record.sample_rate, rate);
+        assert_eq!(record.channels, channels);
+        assert_eq!(record.byte_order, byte_order);
+        if let Some(m) = matrix.filter(|m| !m.contains('\0')) {
+            assert_eq!(record.matrix.as_deref(), Some(m));
+        }
+        drop(device);
+    }
+    mock::clear();
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let rate = u32::from_le_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    let channels = u16::from_le_bytes([data[5], data[6]]) as usize;
+    let byte_order = match data[7] % 3 {
+        0 => Endianness::Native,
+        1 => Endianness::Little,
+        _ => Endianness::Big,
+    };
+    let matrix = match std::str::from_utf8(&data[8..]) {
+        Ok(m) if data[0] & 0x80 != 0 => Some(m),
+        _ => None,
+    };
+
+    match data[0] % 3 {
+        0 => open::<i8>(rate, channels, byte_order, matrix),
+        1 => open::<i16>(rate, channels, byte_order, matrix),
+        _ => open::<i32>(rate, channels, byte_order, matrix),
+    }
+});
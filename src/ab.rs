@@ -0,0 +1,144 @@
+//! Switching between two outputs without reopening.
+//!
+//! Opening a device can take long enough to be heard as a gap, so an `AbDevice` keeps two devices
+//! open in the same format and sends each buffer to whichever is selected. Switching only changes
+//! which device the next buffer goes to, so it is as quick as playback allows. The selection may be
+//! changed from another thread through an `AbSwitch`, for example when a user picks headphones
+//! instead of speakers while audio is playing.
+//!
+//! ```
+//! use ao::{AO, DriverOptions, SampleFormat};
+//! use ao::Endianness::Native;
+//! use ao::ab::{AbDevice, Side};
+//!
+//! fn main() {
+//!     let lib = AO::init();
+//!     let driver = lib.get_driver("").expect("No default driver available");
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 2, Native, None);
+//!
+//!     let speakers = DriverOptions::new();
+//!     let headphones = DriverOptions::new();
+//!     let ab = AbDevice::open(&driver, &format, &speakers, &headphones)
+//!                       .expect("Failed to open devices");
+//!
+//!     let switch = ab.switch();
+//!     ab.play(&[0; 4410]).expect("Playback failed");
+//!     switch.select(Side::B);
+//!     ab.play(&[0; 4410]).expect("Playback failed");
+//! }
+//! ```
+
+use super::{AoError, AoResult, Device, Driver, DriverOptions, Sample, SampleFormat};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// One of the two devices of an `AbDevice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The first device, selected initially.
+    A,
+    /// The second device.
+    B,
+}
+
+/// Changes which device an `AbDevice` plays through.
+///
+/// May be cloned and used from any thread.
+#[derive(Debug, Clone)]
+pub struct AbSwitch {
+    /// Whether `Side::B` is selected.
+    b: Arc<AtomicBool>,
+}
+
+impl AbSwitch {
+    /// Send subsequent buffers to the given device.
+    ///
+    /// A buffer already being played finishes on the device it started on.
+    pub fn select(&self, side: Side) {
+        self.b.store(side == Side::B, Ordering::Release);
+    }
+
+    /// Get the currently selected device.
+    pub fn selected(&self) -> Side {
+        if self.b.load(Ordering::Acquire) {
+            Side::B
+        } else {
+            Side::A
+        }
+    }
+}
+
+/// Plays through one of two open devices, switching between them on demand.
+pub struct AbDevice<'a, S> {
+    a: Device<'a, S>,
+    b: Device<'a, S>,
+    switch: AbSwitch,
+}
+
+impl<'a, S: Sample> AbDevice<'a, S> {
+    /// Combine two already-open devices, initially playing through `a`.
+    ///
+    /// Returns `BadFormat` if the devices differ in sample rate or channel count, since the same
+    /// samples could not be played through both.
+    pub fn new(a: Device<'a, S>, b: Device<'a, S>) -> AoResult<AbDevice<'a, S>> {
+        if a.sample_rate != b.sample_rate || a.channels != b.channels {
+            return Err(AoError::BadFormat);
+        }
+        Ok(AbDevice {
+            a,
+            b,
+            switch: AbSwitch {
+                b: Arc::new(AtomicBool::new(false)),
+            },
+        })
+    }
+
+    /// Open two live devices from the same driver, differing only in options.
+    ///
+    /// For example, two ALSA devices may be opened by setting a different `dev` option for each.
+    pub fn open<F: AsRef<str>>(driver: &Driver<'a>, format: &SampleFormat<S, F>,
+                               a: &DriverOptions, b: &DriverOptions) -> AoResult<AbDevice<'a, S>> {
+        let a = driver.open_live_with(format, a)?;
+        let b = driver.open_live_with(format, b)?;
+        AbDevice::new(a, b)
+    }
+
+    /// Get a handle for switching devices, which may be sent to other threads.
+    pub fn switch(&self) -> AbSwitch {
+        self.switch.clone()
+    }
+
+    /// Send subsequent buffers to the given device.
+    pub fn select(&self, side: Side) {
+        self.switch.select(side);
+    }
+
+    /// Get the currently selected device.
+    pub fn selected(&self) -> Side {
+        self.switch.selected()
+    }
+
+    /// Get one of the devices.
+    pub fn device(&self, side: Side) -> &Device<'a, S> {
+        match side {
+            Side::A => &self.a,
+            Side::B => &self.b,
+        }
+    }
+
+    /// Get one of the devices mutably, such as to change its volume.
+    pub fn device_mut(&mut self, side: Side) -> &mut Device<'a, S> {
+        match side {
+            Side::A => &mut self.a,
+            Side::B => &mut self.b,
+        }
+    }
+
+    /// Play samples through the selected device.
+    ///
+    /// The other device is left idle; as with any device that is not written to, some drivers
+    /// may treat that as an underrun.
+    pub fn play(&self, samples: &[S]) -> AoResult<()> {
+        self.device(self.selected()).play(samples)
+    }
+}
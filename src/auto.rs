@@ -8,7 +8,6 @@
 //! ```
 //! use ao::AO;
 //! use ao::auto::{SampleBuffer, AutoFormatDevice};
-//! use std::error::Error;
 //!
 //! struct StereoBuffer(Vec<(i16, i16)>);
 //! 
@@ -33,15 +32,17 @@
 //!     let data = StereoBuffer(vec![(16383, -16383)]);
 //!     match device.play(&data) {
 //!         Ok(_) => (),
-//!         Err(e) => println!("Playback failed: {}", e.description())
+//!         Err(e) => println!("Playback failed: {}", e)
 //!     }
 //! }
 //! ```
 
-use super::{AoResult, Device, Driver, Sample, SampleFormat};
+use super::{simd, AoError, AoResult, Device, Driver, DriverType, Sample, SampleFormat};
 use super::Endianness;
 use std::marker::PhantomData;
 use std::mem;
+use std::path::Path;
+use std::slice;
 
 /// A buffer containing samples.
 ///
@@ -78,35 +79,45 @@ impl<'a> DeviceFormat<'a> {
         }
     }
 
+    /// Open a device, writing to `file` (and whether to overwrite it) if given, otherwise live.
     fn new(driver: &Driver<'a>, width: usize,
            rate: usize, channels: usize, endianness: Endianness,
-           matrix: Option<&str>) -> AoResult<DeviceFormat<'a>> {
+           matrix: Option<&str>, file: Option<(&Path, bool)>) -> AoResult<DeviceFormat<'a>> {
 
         fn build_format<S: Sample>(rate: usize, channels: usize, order: Endianness,
                                    matrix: Option<&str>) -> SampleFormat<S, &str> {
             SampleFormat {
                 sample_rate: rate,
-                channels: channels,
+                channels,
                 byte_order: order,
-                matrix: matrix,
+                matrix,
+                bits: None,
                 marker: PhantomData
             }
         }
 
+        fn open<'a, S: Sample>(driver: &Driver<'a>, format: &SampleFormat<S, &str>,
+                               file: Option<(&Path, bool)>) -> AoResult<Device<'a, S>> {
+            match file {
+                Some((path, overwrite)) => driver.open_file(format, path, overwrite),
+                None => driver.open_live(format)
+            }
+        }
+
         match width {
             8 => {
                 let format = build_format::<i8>(rate, channels, endianness, matrix);
-                driver.open_live(&format).map(|x| DeviceFormat::Integer8(x))
+                open(driver, &format, file).map(DeviceFormat::Integer8)
             },
             16 => {
                 let format = build_format::<i16>(rate, channels, endianness, matrix);
-                driver.open_live(&format).map(|x| DeviceFormat::Integer16(x))
+                open(driver, &format, file).map(DeviceFormat::Integer16)
             },
             32 => {
                 let format = build_format::<i32>(rate, channels, endianness, matrix);
-                driver.open_live(&format).map(|x| DeviceFormat::Integer32(x))
+                open(driver, &format, file).map(DeviceFormat::Integer32)
             },
-            x => panic!("AutoFormatDevice does not support {}-bit samples", x)
+            _ => Err(AoError::BadFormat)
         }
     }
 }
@@ -115,13 +126,29 @@ impl<'a> DeviceFormat<'a> {
 ///
 /// This device adapter can automatically manage the underlying `Device` to ensure it always has
 /// the correct sample format, so the format of incoming samples may change at runtime.
+///
+/// Live drivers are opened with `open_live`. File output drivers (such as `wav`) are opened with
+/// `open_file`, given a path template with `with_file_template`; since a file cannot change
+/// format partway through, a new file is started whenever the device is reopened.
+///
+/// When the driver prefers a byte order other than that of the buffers, the device is opened in
+/// the driver's order and the samples are swapped here, rather than by libao one at a time.
 pub struct AutoFormatDevice<'a, S> {
     channels: usize,
     sample_rate: usize,
     endianness: Endianness,
+    /// Whether the open device is in the opposite byte order to the buffers.
+    swap: bool,
+    /// Buffer for samples that are swapped, or are not aligned for their type, in `u32`s so it is
+    /// aligned for every sample type.
+    copied: Vec<u32>,
     device: Option<DeviceFormat<'a>>,
     driver: Driver<'a>,
-    matrixes: Vec<S>
+    matrixes: Vec<S>,
+    /// Template for the paths of files written, and whether to overwrite existing files.
+    file_template: Option<(String, bool)>,
+    /// Number of files opened so far.
+    files: usize,
 }
 
 impl<'a, S: AsRef<str>> AutoFormatDevice<'a, S> {
@@ -135,17 +162,34 @@ impl<'a, S: AsRef<str>> AutoFormatDevice<'a, S> {
             channels: 0,
             sample_rate: 0,
             endianness: Endianness::Native,
+            swap: false,
+            copied: Vec::new(),
             device: None,
-            driver: driver,
-            matrixes: matrixes
+            driver,
+            matrixes,
+            file_template: None,
+            files: 0,
         }
     }
 
+    /// Set the paths of the files written when the driver is a file output driver.
+    ///
+    /// In `template`, `{n}` is replaced by the number of files opened before (starting from 0),
+    /// and `{rate}`, `{channels}` and `{bits}` by the format of the file. Existing files are
+    /// replaced if `overwrite` is true. Without a template, playing through a file output driver
+    /// fails with `NotLive`.
+    pub fn with_file_template<T: Into<String>>(mut self, template: T,
+                                               overwrite: bool) -> AutoFormatDevice<'a, S> {
+        self.file_template = Some((template.into(), overwrite));
+        self
+    }
+
     /// Play samples from a dynamic format buffer.
     /// 
     /// The underling device may be reopened, and returns `Err` if
-    /// the format of the buffer is not supported.
-    pub fn play(&mut self, data: &SampleBuffer) -> AoResult<()> {
+    /// the format of the buffer is not supported: `BadFormat` for sample
+    /// widths other than 8, 16 and 32 bits.
+    pub fn play(&mut self, data: &dyn SampleBuffer) -> AoResult<()> {
         let channels = data.channels();
         let sample_rate = data.sample_rate();
         let sample_width = data.sample_width();
@@ -157,20 +201,16 @@ impl<'a, S: AsRef<str>> AutoFormatDevice<'a, S> {
             }
             Some(ref d) => {
                 // Might need to reopen the device
-                if channels != self.channels ||
-                   sample_rate != self.sample_rate ||
-                   endianness != self.endianness ||
-                   sample_width != d.sample_width() {
-                    true
-               } else {
-                   false
-                }
+                channels != self.channels ||
+                    sample_rate != self.sample_rate ||
+                    endianness != self.endianness ||
+                    sample_width != d.sample_width()
             }
         };
         if must_reopen {
-            self.device = Some(try!(
-                self.open_device(sample_width, sample_rate, channels, endianness)
-            ));
+            self.device = Some(
+                self.open_device(sample_width, sample_rate, channels, endianness)?
+            );
         }
 
         // If we didn't early return, our parameters are consistent with the sample buffer.
@@ -179,26 +219,71 @@ impl<'a, S: AsRef<str>> AutoFormatDevice<'a, S> {
         self.endianness = endianness;
 
         // Do the playback
-        let buffer = data.data();
+        let mut buffer = data.data();
+        let align = match sample_width {
+            16 => mem::align_of::<i16>(),
+            32 => mem::align_of::<i32>(),
+            _ => 1
+        };
+        if self.swap || buffer.as_ptr().align_offset(align) != 0 {
+            self.copied.clear();
+            self.copied.resize(buffer.len().div_ceil(4), 0);
+            let copied = unsafe {
+                slice::from_raw_parts_mut(self.copied.as_mut_ptr() as *mut u8, buffer.len())
+            };
+            copied.copy_from_slice(buffer);
+            if self.swap {
+                simd::swap_bytes(copied, sample_width / 8);
+            }
+            buffer = copied;
+        }
         match self.device {
             Some(ref f) => {
-                unsafe {
-                    match *f {
-                        DeviceFormat::Integer8(ref d) => d.play(mem::transmute(buffer)),
-                        DeviceFormat::Integer16(ref d) => d.play(mem::transmute(buffer)),
-                        DeviceFormat::Integer32(ref d) => d.play(mem::transmute(buffer)),
-                    }
+                match *f {
+                    DeviceFormat::Integer8(ref d) => d.play(reinterpret(buffer)),
+                    DeviceFormat::Integer16(ref d) => d.play(reinterpret(buffer)),
+                    DeviceFormat::Integer32(ref d) => d.play(reinterpret(buffer)),
                 }
             },
             None => unreachable!()
         }
-        Ok(())
     }
 
-    fn open_device(&self, width: usize, rate: usize, channels: usize,
+    fn open_device(&mut self, width: usize, rate: usize, channels: usize,
                    endianness: Endianness) -> AoResult<DeviceFormat<'a>> {
-        DeviceFormat::new(&self.driver, width, rate, channels, endianness,
-                          self.matrix_for(channels))
+        let preferred = self.driver.capabilities().preferred_byte_order;
+        let (endianness, swap) = match preferred {
+            Some(order) if width > 8 && order.is_big() != endianness.is_big() => {
+                (order, true)
+            }
+            _ => (endianness, false)
+        };
+        let device = self.open_device_in(width, rate, channels, endianness)?;
+        self.swap = swap;
+        Ok(device)
+    }
+
+    fn open_device_in(&mut self, width: usize, rate: usize, channels: usize,
+                      endianness: Endianness) -> AoResult<DeviceFormat<'a>> {
+        let is_file = self.driver.get_info().is_some_and(|i| i.flavor == DriverType::File);
+        if !is_file {
+            return DeviceFormat::new(&self.driver, width, rate, channels, endianness,
+                                     self.matrix_for(channels), None);
+        }
+
+        let path = match self.file_template {
+            Some((ref template, _)) => template.replace("{n}", &self.files.to_string())
+                                               .replace("{rate}", &rate.to_string())
+                                               .replace("{channels}", &channels.to_string())
+                                               .replace("{bits}", &width.to_string()),
+            None => return Err(AoError::NotLive)
+        };
+        let overwrite = self.file_template.as_ref().is_some_and(|t| t.1);
+        let device = DeviceFormat::new(&self.driver, width, rate, channels, endianness,
+                                       self.matrix_for(channels),
+                                       Some((Path::new(&path), overwrite)))?;
+        self.files += 1;
+        Ok(device)
     }
 
     fn matrix_for(&self, nchannels: usize) -> Option<&str> {
@@ -210,3 +295,14 @@ impl<'a, S: AsRef<str>> AutoFormatDevice<'a, S> {
     }
 }
 
+/// View a raw byte buffer as a slice of whole samples.
+///
+/// Trailing bytes that do not make up a complete sample are ignored. `buffer` must be aligned for
+/// `S`.
+fn reinterpret<S: Sample>(buffer: &[u8]) -> &[S] {
+    assert_eq!(buffer.as_ptr().align_offset(mem::align_of::<S>()), 0, "Misaligned samples");
+    unsafe {
+        slice::from_raw_parts(buffer.as_ptr() as *const S,
+                              buffer.len() / mem::size_of::<S>())
+    }
+}
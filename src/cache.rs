@@ -0,0 +1,128 @@
+//! Reuse of open devices.
+
+use super::{AoResult, Device, Driver, Sample, SampleFormat};
+use libc::c_int;
+use std::collections::HashMap;
+
+/// Identifies the devices a cache can reuse for one another.
+type Key<S> = (c_int, SampleFormat<S, String>);
+
+struct Entry<'a, S> {
+    device: Device<'a, S>,
+    /// Value of the cache's clock when the device was last handed out.
+    used: u64,
+}
+
+/// Hands out open live devices, reusing them for requests in the same format.
+///
+/// Opening a device can take long enough to be heard, so code that switches between a few formats
+/// (such as one stream per format, or an `AutoFormatDevice` alternating between inputs) can keep
+/// the devices open instead. Devices are keyed by driver and `SampleFormat`; once `capacity`
+/// devices are open, the least recently used is closed to make room for another.
+///
+/// ```
+/// use ao::{AO, DeviceCache, SampleFormat};
+/// use ao::Endianness::Native;
+///
+/// fn main() {
+///     let lib = AO::init();
+///     let driver = lib.get_driver("").expect("No default driver available");
+///     let mut cache = DeviceCache::new(2);
+///
+///     let stereo = SampleFormat::<i16, &'static str>::new(44100, 2, Native, None);
+///     cache.get(&driver, &stereo).unwrap().play(&[0; 4410]).unwrap();
+///     // The same device plays this buffer.
+///     cache.get(&driver, &stereo).unwrap().play(&[0; 4410]).unwrap();
+///     assert_eq!(cache.len(), 1);
+/// }
+/// ```
+pub struct DeviceCache<'a, S> {
+    capacity: usize,
+    devices: HashMap<Key<S>, Entry<'a, S>>,
+    /// Counts requests, to order devices by use.
+    clock: u64,
+}
+
+impl<'a, S: Sample> DeviceCache<'a, S> {
+    /// Create an empty cache which keeps up to `capacity` devices open.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> DeviceCache<'a, S> {
+        assert!(capacity > 0, "DeviceCache capacity must be nonzero");
+        DeviceCache {
+            capacity,
+            devices: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Get a live device from `driver` in `format`, opening one if none is cached.
+    ///
+    /// If opening fails the error is returned and the cache is unchanged, except that a device
+    /// may have been closed to make room.
+    pub fn get<F: AsRef<str>>(&mut self, driver: &Driver<'a>, format: &SampleFormat<S, F>)
+            -> AoResult<&Device<'a, S>> {
+        self.clock += 1;
+        let key = key(driver, format);
+        if !self.devices.contains_key(&key) {
+            if self.devices.len() >= self.capacity {
+                self.evict();
+            }
+            let device = driver.open_live(format)?;
+            self.devices.insert(key.clone(), Entry { device, used: 0 });
+        }
+
+        let entry = self.devices.get_mut(&key).unwrap();
+        entry.used = self.clock;
+        Ok(&entry.device)
+    }
+
+    /// Whether a device from `driver` in `format` is open.
+    pub fn contains<F: AsRef<str>>(&self, driver: &Driver<'a>,
+                                   format: &SampleFormat<S, F>) -> bool {
+        self.devices.contains_key(&key(driver, format))
+    }
+
+    /// Take a device out of the cache, leaving it open.
+    pub fn remove<F: AsRef<str>>(&mut self, driver: &Driver<'a>, format: &SampleFormat<S, F>)
+            -> Option<Device<'a, S>> {
+        self.devices.remove(&key(driver, format)).map(|e| e.device)
+    }
+
+    /// Number of devices open.
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Whether no devices are open.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Largest number of devices kept open.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Close every device.
+    pub fn close_all(&mut self) {
+        self.devices.clear();
+    }
+
+    /// Close the least recently used device.
+    fn evict(&mut self) {
+        let oldest = self.devices.iter()
+                                 .min_by_key(|entry| entry.1.used)
+                                 .map(|entry| entry.0.clone());
+        if let Some(key) = oldest {
+            self.devices.remove(&key);
+        }
+    }
+}
+
+fn key<S: Sample, F: AsRef<str>>(driver: &Driver, format: &SampleFormat<S, F>) -> Key<S> {
+    let matrix = format.matrix.as_ref().map(|m| m.as_ref().to_string());
+    let mut owned = SampleFormat::new(format.sample_rate, format.channels, format.byte_order, matrix);
+    owned.bits = format.bits;
+    (driver.id, owned)
+}
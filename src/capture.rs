@@ -0,0 +1,101 @@
+//! Capturing the bytes libao writes.
+//!
+//! A `RawCapture` plays through libao's `raw` file driver into a temporary file, then reads the
+//! file back once the device is closed. This gives exactly the bytes libao produced for a
+//! `SampleFormat` and input, including any byte swapping or channel remapping it performed, which
+//! tests can compare against expected output.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::Native;
+//! use ao::capture::RawCapture;
+//!
+//! fn main() {
+//!     let lib = AO::init();
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 1, Native, None);
+//!     let capture = RawCapture::open(&lib, &format).expect("Failed to open capture");
+//!     capture.play(&[1, 2, 3]).expect("Playback failed");
+//!
+//!     let bytes = capture.finish().expect("Failed to read capture");
+//!     assert_eq!(bytes.len(), 6);
+//! }
+//! ```
+
+use super::{AO, AoError, AoResult, Device, Sample, SampleFormat};
+use options::{DriverConfig, RawOptions};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes captures made by the same process.
+static NEXT_CAPTURE: AtomicUsize = AtomicUsize::new(0);
+
+/// A device whose output is collected in memory.
+///
+/// Dropping the capture without calling `finish` discards the output.
+pub struct RawCapture<'a, S> {
+    /// Always `Some` until the capture is finished.
+    device: Option<Device<'a, S>>,
+    path: PathBuf,
+}
+
+impl<'a, S: Sample> RawCapture<'a, S> {
+    /// Open a capture, with samples written in native byte order.
+    pub fn open<F: AsRef<str>>(lib: &'a AO, format: &SampleFormat<S, F>)
+            -> AoResult<RawCapture<'a, S>> {
+        RawCapture::open_with(lib, format, &RawOptions::default())
+    }
+
+    /// Open a capture with options for the raw driver, such as its output byte order.
+    ///
+    /// Returns `NoDriver` if libao was built without the raw driver.
+    pub fn open_with<F: AsRef<str>>(lib: &'a AO, format: &SampleFormat<S, F>,
+                                    options: &RawOptions) -> AoResult<RawCapture<'a, S>> {
+        let driver = match lib.get_driver(RawOptions::DRIVER) {
+            Some(d) => d,
+            None => return Err(AoError::NoDriver),
+        };
+        options.validate(format)?;
+
+        let name = format!("ao-capture-{}-{}.raw", process::id(),
+                           NEXT_CAPTURE.fetch_add(1, Ordering::Relaxed));
+        let path = env::temp_dir().join(name);
+        let device = driver.open_file_with(format, &path, true, &options.to_options())?;
+        Ok(RawCapture {
+            device: Some(device),
+            path,
+        })
+    }
+
+    /// Play samples into the capture.
+    pub fn play(&self, samples: &[S]) -> AoResult<()> {
+        self.device().play(samples)
+    }
+
+    /// Get the underlying device, for example to check its position.
+    pub fn device(&self) -> &Device<'a, S> {
+        self.device.as_ref().unwrap()
+    }
+
+    /// Get the path of the temporary file being written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Close the device and return everything it wrote.
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        // libao may buffer writes until the device is closed.
+        self.device = None;
+        fs::read(&self.path)
+    }
+}
+
+impl<'a, S> Drop for RawCapture<'a, S> {
+    fn drop(&mut self) {
+        self.device = None;
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -10,6 +10,7 @@ pub const AO_EFILEEXISTS: c_int = 7;
 pub const AO_EBADFORMAT: c_int = 8;
 pub const AO_EFAIL: c_int = 100;
 
+#[cfg(not(feature = "mock"))]
 #[link(name="ao")]
 extern "C" {
     pub fn ao_initialize();
@@ -19,10 +20,12 @@ extern "C" {
     pub fn ao_default_driver_id() -> c_int;
 
     pub fn ao_driver_info(driver_id: c_int) -> *const ao_info;
+    pub fn ao_driver_info_list(driver_count: *mut c_int) -> *const *const ao_info;
     
     pub fn ao_append_option(options: *mut *mut ao_option,
                             key: *const c_char,
                             value: *const c_char) -> c_int;
+    pub fn ao_free_options(options: *mut ao_option);
 
     pub fn ao_open_live(driver_id: c_int,
                         format: *const ao_sample_format,
@@ -40,11 +43,45 @@ extern "C" {
                    num_bytes: u32) -> c_int;
 }
 
+#[cfg(feature = "mock")]
+pub(crate) use mock::{ao_initialize, ao_shutdown, ao_driver_id, ao_default_driver_id, ao_driver_info,
+               ao_driver_info_list, ao_append_option, ao_free_options, ao_open_live, ao_open_file, ao_close, ao_play};
+
+/// Get the error code set by the last failed libao call on this thread.
+#[cfg(not(feature = "mock"))]
+pub fn errno() -> c_int {
+    ::std::io::Error::last_os_error().raw_os_error().unwrap()
+}
+
+/// Clear the error code, so that a failure which does not set it is not
+/// mistaken for an earlier one.
+///
+/// Does nothing on platforms where the error code cannot be reached.
+#[cfg(not(feature = "mock"))]
+pub fn clear_errno() {
+    #[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "fuchsia"))]
+    unsafe {
+        *::libc::__errno_location() = 0;
+    }
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    unsafe {
+        *::libc::__error() = 0;
+    }
+    #[cfg(any(target_os = "android", target_os = "openbsd", target_os = "netbsd"))]
+    unsafe {
+        *::libc::__errno() = 0;
+    }
+}
+
+#[cfg(feature = "mock")]
+pub(crate) use mock::{clear_errno, errno};
+
 #[repr(C)]
 pub struct ao_info {
     pub flavor: c_int,
     pub name: *const c_char,
     pub short_name: *const c_char,
+    pub author: *const c_char,
     pub comment: *const c_char,
     pub preferred_byte_format: c_int,
     pub priority: c_int,
@@ -57,9 +94,9 @@ pub const AO_TYPE_FILE: c_int = 2;
 
 #[repr(C)]
 pub struct ao_option {
-    key: *mut c_char,
-    value: *mut c_char,
-    next: *mut ao_option
+    pub key: *mut c_char,
+    pub value: *mut c_char,
+    pub next: *mut ao_option
 }
 
 // Opaque struct
@@ -0,0 +1,63 @@
+//! Playback of whole frames, with the channel count in the type.
+
+use super::{AoError, AoResult, Device, Sample};
+use std::ops::Deref;
+use std::slice;
+
+/// A device playing frames of `N` channels.
+///
+/// The channel count is checked once, when the device is wrapped, so frames of
+/// the wrong size are rejected by the compiler rather than heard as garbled
+/// audio:
+///
+/// ```compile_fail,E0308
+/// use ao::{FrameDevice, SampleFormat};
+/// use ao::Endianness::Native;
+///
+/// let lib = ao::AO::init();
+/// let format = SampleFormat::<i16, &'static str>::new(44100, 2, Native, None);
+/// let driver = lib.get_driver("").unwrap();
+/// let device: FrameDevice<i16, 2> = FrameDevice::new(driver.open_live(&format).unwrap()).unwrap();
+/// device.play_frames(&[[0i16; 3]]).unwrap();
+/// ```
+///
+/// Other methods of the device are available through `Deref`.
+pub struct FrameDevice<'a, S, const N: usize> {
+    device: Device<'a, S>,
+}
+
+impl<'a, S: Sample, const N: usize> FrameDevice<'a, S, N> {
+    /// Wrap a device opened with `N` channels.
+    ///
+    /// Fails with `BadFormat`, closing the device, if it has any other number
+    /// of channels. `S` must be a single channel, such as `i16`.
+    pub fn new(device: Device<'a, S>) -> AoResult<FrameDevice<'a, S, N>> {
+        if N == 0 || device.channels != N || S::silence().channels() != 1 {
+            return Err(AoError::BadFormat);
+        }
+        Ok(FrameDevice { device })
+    }
+
+    /// Play interleaved frames, as `Device::play` does.
+    pub fn play_frames(&self, frames: &[[S; N]]) -> AoResult<()> {
+        // Arrays are laid out contiguously, so the frames are also a slice of
+        // interleaved samples.
+        let samples = unsafe {
+            slice::from_raw_parts(frames.as_ptr() as *const S, frames.len() * N)
+        };
+        self.device.play(samples)
+    }
+
+    /// Unwrap the device.
+    pub fn into_inner(self) -> Device<'a, S> {
+        self.device
+    }
+}
+
+impl<'a, S, const N: usize> Deref for FrameDevice<'a, S, N> {
+    type Target = Device<'a, S>;
+
+    fn deref(&self) -> &Device<'a, S> {
+        &self.device
+    }
+}
@@ -9,8 +9,6 @@
 //! ```
 //! use ao::{AO, SampleFormat, Driver, Sample};
 //! use ao::Endianness::Native;
-//! use std::error::Error;
-//! use std::num::Float;
 //! use std::path::Path;
 //!
 //! fn main() {
@@ -29,10 +27,12 @@
 //!             let samples: Vec<i16> = (0..44100).map(|i| {
 //!                 ((1.0 / 44100.0 / 440.0 * i as f32).sin() * 32767.0) as i16
 //!             }).collect();
-//!             d.play(&samples);
+//!             if let Err(e) = d.play(&samples) {
+//!                 println!("Playback failed: {}", e);
+//!             }
 //!         }
 //!         Err(e) => {
-//!             println!("Failed to open output file: {}", e.description());
+//!             println!("Failed to open output file: {}", e);
 //!         }
 //!     }
 //! }
@@ -41,25 +41,55 @@
 extern crate libc;
 
 use libc::{c_int, c_char};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::fmt;
-use std::io;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
 use std::marker::PhantomData;
-use std::mem::size_of;
+use std::mem::{self, size_of, size_of_val};
 use std::path::Path;
+use std::slice;
 use std::str;
-use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, OnceLock};
+use std::thread::{self, ThreadId};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::ptr;
+use std::time::{Duration, Instant};
 
 #[allow(non_camel_case_types, dead_code)]
 mod ffi;
+pub mod ab;
 pub mod auto;
+mod cache;
+pub mod capture;
+mod frame;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod multi;
+pub mod net;
+mod open;
+pub mod options;
+pub mod player;
+mod simd;
+pub mod stream;
+mod tap;
+pub mod tee;
+pub mod testing;
+
+pub use cache::DeviceCache;
+pub use frame::FrameDevice;
+pub use open::OpenOptions;
+pub use tap::SampleTap;
 
 /// Output for libao functions that may fail.
 pub type AoResult<T> = Result<T, AoError>;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[repr(isize)]
 /// Result of (most) operations that may fail.
 pub enum AoError {
     /// No driver is available.
@@ -88,11 +118,49 @@ pub enum AoError {
     BadFormat = ffi::AO_EBADFORMAT as isize,
     /// Unspecified error.
     Unknown = ffi::AO_EFAIL as isize,
+    /// Writing samples to the device failed.
+    ///
+    /// The device is unusable and should be closed.
+    Play,
+    /// The driver does not accept an option, or rejected its value.
+    ///
+    /// Holds the key and value of the offending option. Returned in place of
+    /// `BadOption` when the option responsible can be identified.
+    InvalidOption(String, String),
+    /// A channel matrix entry is not one of libao's channel names.
+    ///
+    /// Holds the offending entry. Returned in place of `BadFormat`, before
+    /// the device is opened; see `SampleFormat::check_matrix`.
+    InvalidChannel(String),
+    /// A driver name, file path, channel matrix or driver option contains a
+    /// nul byte, so cannot be passed to libao.
+    ///
+    /// Holds the offending string.
+    InvalidString(String),
+    /// Opening a device did not finish within the time allowed.
+    ///
+    /// Returned by `Driver::open_live_timeout`.
+    Timeout,
+    /// Closing the device failed.
+    ///
+    /// For file output, the end of the file (such as the lengths in a WAV
+    /// header) may not have been written. Returned by `Device::close`; when a
+    /// device is dropped, it is passed to the `set_close_error_handler` hook.
+    Close,
+    /// An error, such as `OpenDevice` or `OpenFile`, with the operating system error that caused
+    /// it.
+    ///
+    /// `Play` and `Close` errors carry the cause libao's driver left in `errno`, if it left one.
+    /// When opening, libao replaces the OS error with its own code, so the cause is found by
+    /// checking the device node or file again. That is only possible for drivers that open a
+    /// known device node (such as OSS with `/dev/dsp`) and for file output; other failures are
+    /// returned without a cause. `source()` returns the `OsError`.
+    System(Box<AoError>, OsError),
 }
 
 impl AoError {
     fn from_errno() -> AoError {
-        match io::Error::last_os_error().raw_os_error().unwrap() as c_int {
+        match ffi::errno() {
             ffi::AO_ENODRIVER => AoError::NoDriver,
             ffi::AO_ENOTFILE => AoError::NotFile,
             ffi::AO_ENOTLIVE => AoError::NotLive,
@@ -104,54 +172,191 @@ impl AoError {
             _ => AoError::Unknown
         }
     }
+
+    /// The error libao reported, without any operating system error attached.
+    ///
+    /// Useful for matching on the kind of failure whether or not its cause was found.
+    pub fn kind(&self) -> &AoError {
+        match *self {
+            AoError::System(ref error, _) => error,
+            _ => self
+        }
+    }
+
+    /// The operating system error that caused this one, if known.
+    pub fn os_error(&self) -> Option<&OsError> {
+        match *self {
+            AoError::System(_, ref cause) => Some(cause),
+            _ => None
+        }
+    }
+
+    /// Attach the OS error found by `cause` to an `OpenDevice` or `OpenFile` error.
+    fn caused_by<F: FnOnce() -> Option<io::Error>>(self, cause: F) -> AoError {
+        match self {
+            AoError::OpenDevice | AoError::OpenFile => {
+                match cause().and_then(|e| e.raw_os_error()) {
+                    Some(code) => AoError::System(Box::new(self), OsError(code)),
+                    None => self
+                }
+            }
+            _ => self
+        }
+    }
 }
 
 impl Error for AoError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.os_error().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+impl fmt::Display for AoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match *self {
-            AoError::NoDriver => "No such driver",
-            AoError::NotFile => "Driver is not a file output device",
-            AoError::NotLive => "Driver is not a live output device",
-            AoError::BadOption => "A valid option key has an invalid value",
-            AoError::OpenDevice => "Cannot open the output device",
-            AoError::OpenFile => "Cannot open the output file",
-            AoError::FileExists => "File for output already exists",
-            AoError::BadFormat => "Requested stream format is not supported",
-            AoError::Unknown => "Unknown error"
+            AoError::NoDriver => write!(f, "No such driver"),
+            AoError::NotFile => write!(f, "Driver is not a file output device"),
+            AoError::NotLive => write!(f, "Driver is not a live output device"),
+            AoError::BadOption => write!(f, "A valid option key has an invalid value"),
+            AoError::OpenDevice => write!(f, "Cannot open the output device"),
+            AoError::OpenFile => write!(f, "Cannot open the output file"),
+            AoError::FileExists => write!(f, "File for output already exists"),
+            AoError::BadFormat => write!(f, "Requested stream format is not supported"),
+            AoError::Unknown => write!(f, "Unknown error"),
+            AoError::Play => write!(f, "Failed to write samples to the device"),
+            AoError::InvalidOption(ref key, ref value) => {
+                write!(f, "Driver option {:?} cannot be set to {:?}", key, value)
+            }
+            AoError::InvalidChannel(ref name) => {
+                write!(f, "Channel matrix entry {:?} is not a channel name", name)
+            }
+            AoError::InvalidString(ref string) => write!(f, "{:?} contains a nul byte", string),
+            AoError::Timeout => write!(f, "Timed out opening the output device"),
+            AoError::Close => write!(f, "Failed to close the device"),
+            AoError::System(ref error, ref cause) => write!(f, "{}: {}", error, cause),
         }
     }
 }
 
-impl fmt::Display for AoError {
+/// An error reported by the operating system, such as a permission or busy device error.
+///
+/// Unlike `io::Error` this can be cloned and compared, as `AoError` requires. Convert it to an
+/// `io::Error` for the error's `ErrorKind`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OsError(i32);
+
+impl OsError {
+    /// The OS error code (`errno` on Unix).
+    pub fn raw_os_error(&self) -> i32 {
+        self.0
+    }
+
+    /// The kind of error, as for `io::Error`.
+    pub fn kind(&self) -> io::ErrorKind {
+        io::Error::from_raw_os_error(self.0).kind()
+    }
+}
+
+impl From<OsError> for io::Error {
+    fn from(error: OsError) -> io::Error {
+        io::Error::from_raw_os_error(error.0)
+    }
+}
+
+impl Error for OsError {}
+
+impl fmt::Display for OsError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        f.write_str(self.description())
+        io::Error::from_raw_os_error(self.0).fmt(f)
     }
 }
 
 /// Type bound for sample formats
 ///
-/// All types that implement `Sample` should be raw enough to permit output
-/// without additional processing. Conspicuously missing from the default impls
-/// is a 24-bit type, simply because there isn't a Rust-native 24-bit type.
-pub trait Sample : Copy {
+/// All types that implement `Sample` are raw enough to permit output without
+/// additional processing. Conspicuously missing from the impls is a 24-bit
+/// type, simply because there isn't a Rust-native 24-bit type; play 24-bit
+/// audio as `i32` with `SampleFormat::bits` set.
+///
+/// The trait is sealed, so the integer types and two-channel arrays of them
+/// are its only implementations. Samples are read from raw bytes (by
+/// `Device::play_from_reader`, `AutoFormatDevice` and `SampleTap`), which is
+/// only sound because every bit pattern is a valid value of these types.
+pub trait Sample : Copy + sealed::Sealed {
     /// Number of channels each value of this type contains.
     fn channels(&self) -> usize;
+    /// Multiply this value by a linear gain factor.
+    ///
+    /// Results that do not fit in the sample type saturate at its limits.
+    fn scale(&self, gain: f32) -> Self;
+    /// Multiply this value by a linear gain factor, with dither.
+    ///
+    /// `noise` is called once for every channel, and its result (in units of
+    /// the least significant bit) is added before the scaled value is
+    /// rounded to the sample type.
+    fn scale_dithered<N: FnMut() -> f32>(&self, gain: f32, noise: &mut N) -> Self;
+    /// A value of this type that produces no sound.
+    fn silence() -> Self;
+    /// Append each of `samples`, multiplied by `gain` as `scale` does, to `out`.
+    ///
+    /// Implementations may scale many samples at once, but must produce the
+    /// same results as `scale`.
+    fn scale_into(samples: &[Self], gain: f32, out: &mut Vec<Self>) {
+        out.extend(samples.iter().map(|s| s.scale(gain)));
+    }
+}
+
+mod sealed {
+    /// Keeps `Sample` from being implemented outside this crate.
+    pub trait Sealed {}
 }
 
 macro_rules! sample_impl(
-    ($t:ty) => (
+    ($t:ty) => (sample_impl!($t, {}););
+    ($t:ty, { $($extra:tt)* }) => (
+        impl sealed::Sealed for $t {}
         impl Sample for $t {
             fn channels(&self) -> usize { 1 }
+            fn scale(&self, gain: f32) -> $t {
+                (*self as f64 * gain as f64) as $t
+            }
+            fn scale_dithered<N: FnMut() -> f32>(&self, gain: f32,
+                                                 noise: &mut N) -> $t {
+                (*self as f64 * gain as f64 + noise() as f64).round() as $t
+            }
+            fn silence() -> $t { 0 }
+            $($extra)*
         }
     );
     (channels $w:expr) => (
+        impl<S: Sample> sealed::Sealed for [S; $w] {}
         impl<S: Sample> Sample for [S; $w] {
             fn channels(&self) -> usize { $w }
+            fn scale(&self, gain: f32) -> [S; $w] {
+                let mut out = *self;
+                for s in out.iter_mut() {
+                    *s = s.scale(gain);
+                }
+                out
+            }
+            fn scale_dithered<N: FnMut() -> f32>(&self, gain: f32,
+                                                 noise: &mut N) -> [S; $w] {
+                let mut out = *self;
+                for s in out.iter_mut() {
+                    *s = s.scale_dithered(gain, noise);
+                }
+                out
+            }
+            fn silence() -> [S; $w] { [S::silence(); $w] }
         }
     )
 );
 sample_impl!(i8);
-sample_impl!(i16);
+sample_impl!(i16, {
+    fn scale_into(samples: &[i16], gain: f32, out: &mut Vec<i16>) {
+        simd::scale_i16(samples, gain, out)
+    }
+});
 sample_impl!(i32);
 sample_impl!(channels 2);
 
@@ -173,6 +378,15 @@ pub struct SampleFormat<T, S> {
     /// Refer to the [`matrix` documentation](https://www.xiph.org/ao/doc/ao_sample_format.html)
     /// for additional information and examples.
     pub matrix: Option<S>,
+    /// Number of valid bits in each sample, if fewer than the sample type
+    /// holds, such as 12-bit audio carried in `i16`.
+    ///
+    /// libao stores each sample in the fewest whole bytes that hold this many
+    /// bits, which must be the size of a sample of `T`. In particular libao
+    /// packs 24-bit samples into three bytes, so they cannot be carried in
+    /// `i32`. `None`, the default, declares every bit of the sample type.
+    /// Devices check the value when they are opened; see `check_bits`.
+    pub bits: Option<usize>,
     marker: PhantomData<T>
 }
 
@@ -181,40 +395,133 @@ impl<T: Sample, S: AsRef<str>> SampleFormat<T, S> {
     pub fn new(sample_rate: usize, channels: usize, byte_order: Endianness,
                matrix: Option<S>) -> SampleFormat<T, S> {
         SampleFormat {
-            sample_rate: sample_rate,
-            channels: channels,
-            byte_order: byte_order,
-            matrix: matrix,
+            sample_rate,
+            channels,
+            byte_order,
+            matrix,
+            bits: None,
             marker: PhantomData
         }
     }
 
-    fn with_native<F, U>(&self, f: F) -> U
-            where F: FnOnce(*const ffi::ao_sample_format) -> U {
-        let sample_size = size_of::<T>() * 8;
+    /// Check that every entry of the channel matrix is a channel name libao
+    /// knows.
+    ///
+    /// The names are `L`, `R`, `C`, `M`, `CL`, `CR`, `BL`, `BR`, `BC`, `SL`,
+    /// `SR`, `LFE`, `A1` to `A4` and `X`. Fails with `InvalidChannel` for the
+    /// first entry that is not, where libao would drop the channel or fail
+    /// with `BadFormat`, or with `InvalidString` if the matrix contains a nul
+    /// byte. Devices are checked when they are opened.
+    pub fn check_matrix(&self) -> AoResult<()> {
+        let matrix = match self.matrix {
+            Some(ref m) => m.as_ref(),
+            None => return Ok(())
+        };
+        if matrix.contains('\0') {
+            return Err(AoError::InvalidString(matrix.to_owned()));
+        }
+        match matrix.split(',').map(str::trim).find(|name| !CHANNEL_NAMES.contains(name)) {
+            Some(name) => Err(AoError::InvalidChannel(name.to_owned())),
+            None => Ok(())
+        }
+    }
 
+    /// Build the native sample format and pass it to `f`.
+    ///
+    /// Fails with `InvalidString` if the matrix contains a nul byte.
+    fn with_native<F, U>(&self, f: F) -> AoResult<U>
+            where F: FnOnce(*const ffi::ao_sample_format) -> U {
         let matrix: Option<CString> = match self.matrix {
             None => None,
-            Some(ref s) => CString::new(s.as_ref()).ok()
+            Some(ref s) => match CString::new(s.as_ref()) {
+                Ok(s) => Some(s),
+                Err(_) => return Err(AoError::InvalidString(s.as_ref().to_owned()))
+            }
         };
         // The caller of ao_open_* functions retains ownership of the ao_format
         // it passes in, but the native representation owns a raw C string.
         // We must ensure the raw C string is freed, so the actual
         // ao_sample_format never leaves this scope.
         let native = ffi::ao_sample_format {
-            bits: sample_size as c_int,
+            bits: self.bits() as c_int,
             rate: self.sample_rate as c_int,
             channels: self.channels as c_int,
             byte_format: self.byte_order as c_int,
-            matrix: matrix.map_or(ptr::null(), |cs| cs.as_ptr())
+            matrix: matrix.as_ref().map_or(ptr::null(), |cs| cs.as_ptr())
         };
 
-        f(&native as *const _)
+        Ok(f(&native as *const _))
+    }
+}
+
+impl<T: Sample, S> SampleFormat<T, S> {
+    /// Number of bits in each sample, as declared to libao.
+    pub fn bits(&self) -> usize {
+        self.bits.unwrap_or_else(Self::sample_bits)
+    }
+
+    /// Bits in a sample of `T`, which may hold several channels.
+    fn sample_bits() -> usize {
+        size_of::<T>() * 8 / T::silence().channels()
+    }
+
+    /// Check that the declared number of bits fits the sample type.
+    ///
+    /// Fails with `BadFormat` if `bits` is zero, or would be stored by libao
+    /// in more or fewer bytes than a sample of `T` occupies.
+    pub fn check_bits(&self) -> AoResult<()> {
+        match self.bits {
+            Some(bits) if bits == 0 || bits.div_ceil(8) * 8 != Self::sample_bits() => {
+                Err(AoError::BadFormat)
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+impl<T, S: Clone> Clone for SampleFormat<T, S> {
+    fn clone(&self) -> SampleFormat<T, S> {
+        SampleFormat {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            byte_order: self.byte_order,
+            matrix: self.matrix.clone(),
+            bits: self.bits,
+            marker: PhantomData
+        }
+    }
+}
+
+impl<T, S: PartialEq> PartialEq for SampleFormat<T, S> {
+    fn eq(&self, other: &SampleFormat<T, S>) -> bool {
+        self.sample_rate == other.sample_rate &&
+            self.channels == other.channels &&
+            self.byte_order == other.byte_order &&
+            self.matrix == other.matrix &&
+            self.bits == other.bits
+    }
+}
+
+impl<T, S: Eq> Eq for SampleFormat<T, S> {}
+
+impl<T, S: Hash> Hash for SampleFormat<T, S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sample_rate.hash(state);
+        self.channels.hash(state);
+        self.byte_order.hash(state);
+        self.matrix.hash(state);
+        self.bits.hash(state);
     }
 }
 
+/// Channel names libao accepts in a matrix.
+const CHANNEL_NAMES: [&str; 17] = [
+    "L", "R", "C", "M", "CL", "CR", "BL", "BR", "BC", "SL", "SR", "LFE",
+    "A1", "A2", "A3", "A4", "X",
+];
+
 /// Sample byte ordering.
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Endianness {
     /// Least-significant byte first
     Little = ffi::AO_FMT_LITTLE as isize,
@@ -224,6 +531,23 @@ pub enum Endianness {
     Native = ffi::AO_FMT_NATIVE as isize,
 }
 
+impl Endianness {
+    /// Whether samples in this order are stored most-significant byte first on this machine.
+    pub(crate) fn is_big(self) -> bool {
+        match self {
+            Endianness::Big => true,
+            Endianness::Little => false,
+            Endianness::Native => cfg!(target_endian = "big"),
+        }
+    }
+
+    /// Whether samples in this order must have their bytes reversed to be read as numbers on
+    /// this machine.
+    pub(crate) fn is_foreign(self) -> bool {
+        self.is_big() != cfg!(target_endian = "big")
+    }
+}
+
 /// Library owner.
 ///
 /// Initialization of this object loads plugins and system/user configuration
@@ -231,21 +555,168 @@ pub enum Endianness {
 ///
 /// Behind the scenes, this object controls initialization of libao. It should
 /// be created only from the main thread of your application, due to bugs in
-/// some output drivers that can cause segfaults on thread exit.
+/// some output drivers that can cause segfaults on thread exit. Acquire a
+/// `MainThreadToken` in `main` to have that checked.
 pub struct AO;
 
-static mut FFI_INITIALIZED: AtomicBool = ATOMIC_BOOL_INIT;
+/// Proof that the current thread is the application's main thread.
+///
+/// Acquiring a token checks that the calling thread is the process's initial
+/// thread, on Linux and Apple platforms where that can be asked of the OS;
+/// elsewhere the first thread to acquire a token is trusted to be it. From
+/// then on, creating an `AO` on any other thread panics with a clear message
+/// rather than risking a crash when that thread exits. A token cannot be sent
+/// to another thread, so `AO::init_on` needs no check at all.
+///
+/// ```
+/// use ao::{AO, MainThreadToken};
+///
+/// fn main() {
+///     let token = MainThreadToken::acquire();
+///     let lib = AO::init_on(&token);
+///     # drop(lib);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MainThreadToken {
+    marker: PhantomData<*const ()>,
+}
+
+/// The thread that acquired the first `MainThreadToken`.
+static MAIN_THREAD: OnceLock<ThreadId> = OnceLock::new();
+
+impl MainThreadToken {
+    /// Record the calling thread as the main thread, and get a token for it.
+    ///
+    /// Call this early in `main`. Panics if the calling thread is not the
+    /// main thread, or where that cannot be checked, if a token was already
+    /// acquired on a different thread.
+    pub fn acquire() -> MainThreadToken {
+        let current = thread::current().id();
+        if is_main_thread() == Some(false) || *MAIN_THREAD.get_or_init(|| current) != current {
+            panic!("MainThreadToken acquired on a thread other than the main thread");
+        }
+        MainThreadToken {
+            marker: PhantomData
+        }
+    }
+}
+
+/// Whether the calling thread is the process's initial thread, whose thread
+/// id is the process id.
+#[cfg(target_os = "linux")]
+fn is_main_thread() -> Option<bool> {
+    Some(unsafe { libc::syscall(libc::SYS_gettid) == libc::getpid() as libc::c_long })
+}
+
+/// Whether the calling thread is the process's initial thread.
+#[cfg(target_vendor = "apple")]
+fn is_main_thread() -> Option<bool> {
+    Some(unsafe { libc::pthread_main_np() } != 0)
+}
+
+/// The main thread cannot be identified elsewhere.
+#[cfg(not(any(target_os = "linux", target_vendor = "apple")))]
+fn is_main_thread() -> Option<bool> {
+    None
+}
+
+/// Whether an `AO` exists.
+///
+/// The lock is held while libao initializes and shuts down, so an `AO` created
+/// while another is being dropped waits for shutdown to finish.
+static LIBRARY: Mutex<bool> = Mutex::new(false);
+
+fn library() -> MutexGuard<'static, bool> {
+    // The flag is only changed after libao has, so it is accurate even if a
+    // panic poisoned the lock.
+    LIBRARY.lock().unwrap_or_else(|e| e.into_inner())
+}
 
 impl AO {
     /// Get the `AO`
+    ///
+    /// libao may be initialized again after a previous `AO` has been dropped.
+    ///
+    /// Panics if an `AO` already exists, or if a `MainThreadToken` has been
+    /// acquired and this is not the main thread.
     pub fn init() -> AO {
+        AO::try_init().expect("Attempted multiple instantiation of ao::AO")
+    }
+
+    /// Get the `AO` on the main thread.
+    ///
+    /// The token shows this is the main thread, so no check is made.
+    /// Panics if an `AO` already exists.
+    pub fn init_on(token: &MainThreadToken) -> AO {
+        let _ = token;
+        AO::initialize().expect("Attempted multiple instantiation of ao::AO")
+    }
+
+    /// Get the `AO`, or `None` if one already exists.
+    ///
+    /// Panics if a `MainThreadToken` has been acquired and this is not the
+    /// main thread.
+    pub fn try_init() -> Option<AO> {
+        if MAIN_THREAD.get().is_some_and(|&main| main != thread::current().id()) {
+            panic!("ao::AO must be created on the main thread");
+        }
+        AO::initialize()
+    }
+
+    /// Initialize libao unless an `AO` already exists, whatever the thread.
+    fn initialize() -> Option<AO> {
+        let mut live = library();
+        if *live {
+            return None;
+        }
         unsafe {
-            if FFI_INITIALIZED.compare_and_swap(false, true, Ordering::AcqRel) {
-                panic!("Attempted multiple instantiation of ao::AO")
-            }
             ffi::ao_initialize();
-        };
-        AO
+        }
+        *live = true;
+        Some(AO)
+    }
+
+    /// Iterate over every driver libao has loaded.
+    ///
+    /// This includes drivers that cannot currently be opened, such as those
+    /// for a sound server that is not running.
+    pub fn drivers(&self) -> impl Iterator<Item = Driver<'_>> {
+        let mut count = 0;
+        unsafe {
+            ffi::ao_driver_info_list(&mut count);
+        }
+        (0..count.max(0)).map(|id| Driver {
+            id,
+            marker: PhantomData
+        })
+    }
+
+    /// Iterate over the live output drivers libao has loaded.
+    pub fn live_drivers(&self) -> impl Iterator<Item = Driver<'_>> {
+        self.drivers_of(DriverType::Live)
+    }
+
+    /// Iterate over the file output drivers libao has loaded.
+    pub fn file_drivers(&self) -> impl Iterator<Item = Driver<'_>> {
+        self.drivers_of(DriverType::File)
+    }
+
+    fn drivers_of(&self, flavor: DriverType) -> impl Iterator<Item = Driver<'_>> {
+        self.drivers().filter(move |d| d.get_info().is_some_and(|i| i.flavor == flavor))
+    }
+
+    /// Get a live output driver to play through when the user has not chosen one.
+    ///
+    /// This is the default driver if it is a live driver, and otherwise the
+    /// live driver with the highest priority. Returns `None` if there are no
+    /// live drivers.
+    pub fn find_live_default(&self) -> Option<Driver<'_>> {
+        let default = self.get_driver("")
+                          .filter(|d| d.get_info().is_some_and(|i| i.flavor == DriverType::Live));
+        default.or_else(|| {
+            self.live_drivers().max_by_key(|d| d.get_info().map_or(0, |i| i.priority))
+        })
     }
 
     /// Gets the specified output driver or default.
@@ -259,11 +730,23 @@ impl AO {
     /// configuration, otherwise it will be automatically chosen to be a live
     /// output supported by the current platform. This implies that the default
     /// driver will not necessarily be a live output.
-    pub fn get_driver<'a>(&'a self, name: &str) -> Option<Driver<'a>> {
-        let id = if name != "" {
+    ///
+    /// libao's standard drivers may be named with `KnownDriver` instead of a
+    /// string.
+    pub fn get_driver<'a, N: AsRef<str>>(&'a self, name: N) -> Option<Driver<'a>> {
+        self.find_driver(name).ok()
+    }
+
+    /// Gets the specified output driver or default, as `get_driver` does.
+    ///
+    /// Fails with `NoDriver` if the driver is not available, or with
+    /// `InvalidString` if `name` contains a nul byte.
+    pub fn find_driver<'a, N: AsRef<str>>(&'a self, name: N) -> AoResult<Driver<'a>> {
+        let name = name.as_ref();
+        let id = if !name.is_empty() {
             let cs = match CString::new(name) {
                 Ok(s) => s,
-                Err(_) => return None
+                Err(_) => return Err(AoError::InvalidString(name.to_owned()))
             };
             unsafe {
                 ffi::ao_driver_id(cs.as_ptr())
@@ -275,32 +758,187 @@ impl AO {
         };
 
         if id == -1 {
-            None
+            Err(AoError::NoDriver)
         } else {
-            Some(Driver {
-                id: id,
+            Ok(Driver {
+                id,
                 marker: PhantomData
             })
         }
     }
+
+    /// Set whether devices print extra information about what their driver
+    /// is doing, unless overridden by `DriverOptions::verbose`.
+    pub fn set_default_verbose(&self, on: bool) {
+        set_default_diagnostic(0, on);
+    }
+
+    /// Set whether devices suppress all of libao's messages, unless
+    /// overridden by `DriverOptions::quiet`.
+    pub fn set_default_quiet(&self, on: bool) {
+        set_default_diagnostic(1, on);
+    }
+
+    /// Set whether devices print detailed debugging information, unless
+    /// overridden by `DriverOptions::debug`.
+    pub fn set_default_debug(&self, on: bool) {
+        set_default_diagnostic(2, on);
+    }
+
+    /// Set an option to be passed to every device opened from now on.
+    ///
+    /// Options given when opening a device take precedence. Unlike those,
+    /// a default option is skipped for drivers that do not accept its key,
+    /// so options for several drivers may be set at once. Options that work
+    /// with any driver, such as `client_name`, may be set with
+    /// `set_default_options`.
+    pub fn set_default_option<K: Into<String>, V: Into<String>>(&self, key: K, value: V) {
+        let mut defaults = default_options();
+        let options = defaults.take().unwrap_or_default().set(key, value);
+        *defaults = Some(options);
+    }
+
+    /// Replace every default option, as set by `set_default_option`.
+    ///
+    /// Diagnostic toggles set on `options` override those set with
+    /// `set_default_verbose` and friends. Pass `DriverOptions::new()` to clear
+    /// the defaults.
+    pub fn set_default_options(&self, options: DriverOptions) {
+        *default_options() = Some(options).filter(|o| !o.is_empty());
+    }
+
+    /// Open a device on libao's `null` driver, which discards everything
+    /// written to it.
+    ///
+    /// The null driver needs no sound hardware, so this exercises the whole
+    /// open, play and close path on headless machines such as CI servers.
+    ///
+    /// Returns `NoDriver` if libao was built without the null driver.
+    pub fn null_device<T: Sample, S: AsRef<str>>(&self,
+            format: &SampleFormat<T, S>) -> AoResult<Device<'_, T>> {
+        match self.get_driver(KnownDriver::Null) {
+            Some(driver) => driver.open_live(format),
+            None => Err(AoError::NoDriver)
+        }
+    }
+}
+
+fn set_default_diagnostic(index: usize, on: bool) {
+    if on {
+        DEFAULT_DIAGNOSTICS.fetch_or(1 << index, Ordering::Relaxed);
+    } else {
+        DEFAULT_DIAGNOSTICS.fetch_and(!(1 << index), Ordering::Relaxed);
+    }
 }
 
 impl Drop for AO {
     fn drop(&mut self) {
+        let mut live = library();
         unsafe {
             ffi::ao_shutdown();
-            FFI_INITIALIZED.store(false, Ordering::Release);
+        }
+        *live = false;
+    }
+}
+
+/// The drivers distributed with libao.
+///
+/// Which are available depends on the platform and how libao was built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KnownDriver {
+    /// PulseAudio sound server.
+    Pulse,
+    /// Advanced Linux Sound Architecture.
+    Alsa,
+    /// Open Sound System.
+    Oss,
+    /// Enlightened Sound Daemon.
+    Esd,
+    /// aRts sound server of older KDE versions.
+    Arts,
+    /// Network Audio System.
+    Nas,
+    /// OpenBSD's sndio.
+    Sndio,
+    /// RoarAudio sound server.
+    Roar,
+    /// Core Audio on macOS.
+    MacOsX,
+    /// Windows multimedia (waveOut).
+    Wmm,
+    /// Sun audio on Solaris and NetBSD.
+    Sun,
+    /// Discards all output.
+    Null,
+    /// Microsoft WAVE files.
+    Wav,
+    /// Sun au files.
+    Au,
+    /// Raw sample files.
+    Raw,
+}
+
+impl KnownDriver {
+    /// Every known driver.
+    pub const ALL: [KnownDriver; 15] = [
+        KnownDriver::Pulse, KnownDriver::Alsa, KnownDriver::Oss, KnownDriver::Esd,
+        KnownDriver::Arts, KnownDriver::Nas, KnownDriver::Sndio, KnownDriver::Roar,
+        KnownDriver::MacOsX, KnownDriver::Wmm, KnownDriver::Sun, KnownDriver::Null,
+        KnownDriver::Wav, KnownDriver::Au, KnownDriver::Raw,
+    ];
+
+    /// Get the short name libao knows the driver by.
+    pub fn short_name(self) -> &'static str {
+        match self {
+            KnownDriver::Pulse => "pulse",
+            KnownDriver::Alsa => "alsa",
+            KnownDriver::Oss => "oss",
+            KnownDriver::Esd => "esd",
+            KnownDriver::Arts => "arts",
+            KnownDriver::Nas => "nas",
+            KnownDriver::Sndio => "sndio",
+            KnownDriver::Roar => "roar",
+            KnownDriver::MacOsX => "macosx",
+            KnownDriver::Wmm => "wmm",
+            KnownDriver::Sun => "sun",
+            KnownDriver::Null => "null",
+            KnownDriver::Wav => "wav",
+            KnownDriver::Au => "au",
+            KnownDriver::Raw => "raw",
         }
     }
+
+    /// Find the known driver with a short name.
+    pub fn from_short_name(name: &str) -> Option<KnownDriver> {
+        KnownDriver::ALL.iter().cloned().find(|d| d.short_name() == name)
+    }
+}
+
+impl AsRef<str> for KnownDriver {
+    fn as_ref(&self) -> &str {
+        self.short_name()
+    }
+}
+
+impl fmt::Display for KnownDriver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.short_name())
+    }
 }
 
 /// The output type of a driver.
-#[derive(Debug, Clone, Copy)]
+///
+/// Later versions of libao may add other types, so matches should include a
+/// wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum DriverType {
     /// Live playback, such as a local sound card.
     Live,
     /// File output, such as to a `wav` file on disk.
-    File
+    File,
+    /// A type this crate does not know, holding libao's value for it.
+    Unknown(i32),
 }
 
 impl DriverType {
@@ -308,7 +946,7 @@ impl DriverType {
         match n {
             ffi::AO_TYPE_FILE => DriverType::File,
             ffi::AO_TYPE_LIVE => DriverType::Live,
-            n => panic!("Invalid AO_TYPE_*: {}", n)
+            n => DriverType::Unknown(n)
         }
     }
 }
@@ -329,91 +967,749 @@ pub struct DriverInfo<'a> {
     pub short_name: &'a str,
     /// A driver-specified comment.
     pub comment: Option<&'a str>,
+    /// How strongly libao prefers the driver when choosing a default.
+    ///
+    /// Higher values are preferred. File drivers have priority zero and are
+    /// only used when asked for by name.
+    pub priority: i32,
+    /// Driver the information describes, to look up its options.
+    id: c_int,
 }
 
-/// An output driver.
+impl<'a> DriverInfo<'a> {
+    /// Iterate over the keys of the options the driver accepts.
+    ///
+    /// Options listed here are passed to the driver with `DriverOptions`.
+    /// Drivers usually list libao's common options, such as `matrix` and
+    /// `verbose`, as well as their own.
+    pub fn options(&self) -> impl Iterator<Item = &'a str> {
+        let keys: &'a [*const c_char] = unsafe {
+            let info = ffi::ao_driver_info(self.id);
+            if info.is_null() || (*info).options.is_null() || (*info).option_count <= 0 {
+                &[]
+            } else {
+                std::slice::from_raw_parts((*info).options, (*info).option_count as usize)
+            }
+        };
+        keys.iter().filter_map(|&k| unsafe {
+            str::from_utf8(CStr::from_ptr(k).to_bytes()).ok()
+        })
+    }
+}
+
+/// Driver-specific options to pass when opening a device.
 ///
-/// This is an opaque handle.
-pub struct Driver<'a> {
-    id: c_int,
-    marker: PhantomData<&'a ()>
+/// Each driver accepts its own set of options, such as `dev` to select an
+/// ALSA device or `server` for PulseAudio; see the libao documentation for
+/// the full list, or `DriverInfo::options`. Opening a device fails with
+/// `InvalidOption` if the driver does not list a key, or if it rejects a
+/// value and the option responsible can be identified; otherwise a rejected
+/// value fails with `BadOption`.
+///
+/// ```
+/// use ao::DriverOptions;
+///
+/// let options = DriverOptions::new().set("dev", "hw:1").set("buffer_time", "100");
+/// assert_eq!(options.get("dev"), Some("hw:1"));
+/// ```
+///
+/// libao's own diagnostics, printed to standard error, are controlled by the
+/// `verbose`, `quiet` and `debug` toggles. Toggles not set on the options use
+/// the defaults set with `AO::set_default_verbose` and friends.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriverOptions {
+    options: Vec<(String, String)>,
+    /// Explicit settings for each of `DIAGNOSTICS`.
+    diagnostics: [Option<bool>; 3],
+    client_name: Option<String>,
+    stream_name: Option<String>,
+    /// An option whose value cannot be passed to libao, set with `reject`.
+    rejected: Option<(String, String)>,
 }
 
-impl<'a> Driver<'a> {
-    /// Get the `DriverInfo` corresponding to this `Driver`.
-    pub fn get_info(& self) -> Option<DriverInfo<'a>> {
-        let id = self.id;
+/// Keys drivers use for the name of the client application.
+const CLIENT_NAME_KEYS: [&str; 2] = ["client_name", "client"];
 
-        /// Turn a non-null C string pointer into static string slice.
-        ///
-        /// Panics if the string is not valid UTF-8.
-        unsafe fn sstr<'z>(s: *const c_char) -> &'z str {
-            str::from_utf8(CStr::from_ptr(s).to_bytes()).unwrap()
-        }
+/// Keys drivers use for the name of a stream.
+const STREAM_NAME_KEYS: [&str; 2] = ["stream_name", "stream"];
 
-        unsafe {
-            let info = ffi::ao_driver_info(id);
-            if info.is_null() {
-                None
-            } else {
-                let ref info = *info;
-                Some(DriverInfo {
-                    name: sstr(info.name),
-                    short_name: sstr(info.short_name),
-                    comment: if info.comment.is_null() {
-                        None
-                    } else {
-                        Some(sstr(info.comment))
-                    },
-                    flavor: DriverType::from_c_int(info.flavor),
-                })
-            }
+/// Keys of libao's diagnostic options, which take effect whatever their value.
+const DIAGNOSTICS: [&str; 3] = ["verbose", "quiet", "debug"];
+
+/// Keys libao handles itself for every driver.
+const COMMON_OPTIONS: [&str; 4] = ["matrix", "verbose", "quiet", "debug"];
+
+/// Default settings for `DIAGNOSTICS`, one bit for each.
+static DEFAULT_DIAGNOSTICS: AtomicUsize = AtomicUsize::new(0);
+
+type CloseErrorHandler = Arc<dyn Fn(&AoError) + Send + Sync>;
+
+/// Called with errors closing devices that are dropped, set with `set_close_error_handler`.
+static CLOSE_ERROR_HANDLER: Mutex<Option<CloseErrorHandler>> = Mutex::new(None);
+
+/// Set a function to be called when a device fails to close as it is dropped.
+///
+/// `Drop` cannot return an error, so without a handler such failures go
+/// unnoticed; a file output that could not be finished is the usual cause.
+/// The handler runs on the thread dropping the device, and replaces any
+/// previously set. Use `Device::close` to get the error directly instead.
+pub fn set_close_error_handler<F>(handler: F) where F: Fn(&AoError) + Send + Sync + 'static {
+    *close_error_handler() = Some(Arc::new(handler));
+}
+
+fn close_error_handler() -> MutexGuard<'static, Option<CloseErrorHandler>> {
+    CLOSE_ERROR_HANDLER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Options merged into every device opened, set with `AO::set_default_option`.
+static DEFAULT_OPTIONS: Mutex<Option<DriverOptions>> = Mutex::new(None);
+
+fn default_options() -> MutexGuard<'static, Option<DriverOptions>> {
+    DEFAULT_OPTIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl DriverOptions {
+    /// Create an empty set of options.
+    pub fn new() -> DriverOptions {
+        DriverOptions::default()
+    }
+
+    /// Set an option, replacing any previous value for the same key.
+    pub fn set<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> DriverOptions {
+        let key = key.into();
+        let value = value.into();
+        match self.options.iter_mut().find(|(k, _)| *k == key) {
+            Some(option) => option.1 = value,
+            None => self.options.push((key, value)),
         }
+        self
     }
 
-    /// Open a live output device.
-    ///
-    /// Returns `NotLive` if the specified driver is not a live output driver.
-    /// In this case, open the device as a file output instead.
-    pub fn open_live<T: Sample, S: AsRef<str>>(&self,
-            format: &SampleFormat<T, S>) -> AoResult<Device<'a, T>> {
-        let handle = format.with_native(|f| unsafe {
-            ffi::ao_open_live(self.id, f, ptr::null())
-        });
+    /// Get the value of an option, if set.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options.iter().find(|(k, _)| k == key).map(|(_, v)| &v[..])
+    }
 
-        Device::<'a, T>::init(handle)
+    /// Iterate over the options as key-value pairs, in the order they were set.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.options.iter().map(|(k, v)| (&k[..], &v[..]))
     }
 
-    /// Open a file output device.
-    ///
-    /// `path` specifies the file to write to, and `overwrite` will
-    /// automatically replace any existing file if `true`.
+    /// Whether no options are set.
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty() && self.diagnostics.iter().all(Option::is_none) &&
+            self.client_name.is_none() && self.stream_name.is_none() && self.rejected.is_none()
+    }
+
+    /// Record an option whose value cannot be represented, so that opening fails with
+    /// `InvalidOption` for it rather than passing libao something else.
+    pub(crate) fn reject<K, V>(mut self, key: K, value: V) -> DriverOptions
+            where K: Into<String>, V: Into<String> {
+        self.rejected = Some((key.into(), value.into()));
+        self
+    }
+
+    /// Print extra information about what the driver is doing.
+    pub fn verbose(mut self, on: bool) -> DriverOptions {
+        self.diagnostics[0] = Some(on);
+        self
+    }
+
+    /// Suppress all of libao's messages, including errors.
+    pub fn quiet(mut self, on: bool) -> DriverOptions {
+        self.diagnostics[1] = Some(on);
+        self
+    }
+
+    /// Print detailed debugging information, more than `verbose`.
+    pub fn debug(mut self, on: bool) -> DriverOptions {
+        self.diagnostics[2] = Some(on);
+        self
+    }
+
+    /// Name the application to the sound server, for display in mixers.
+    ///
+    /// Unlike options set with `set`, this works with any driver: it is
+    /// passed under whichever key the driver uses for a client name, such as
+    /// PulseAudio's `client_name`, and dropped for drivers without one.
+    pub fn client_name<N: Into<String>>(mut self, name: N) -> DriverOptions {
+        self.client_name = Some(name.into());
+        self
+    }
+
+    /// Name the stream to the sound server, for display in mixers.
     ///
-    /// Returns `NotFile` if the requested driver is not a file output driver.
+    /// As with `client_name`, this is passed under whichever key the driver
+    /// uses for a stream name, and dropped for drivers without one.
+    pub fn stream_name<N: Into<String>>(mut self, name: N) -> DriverOptions {
+        self.stream_name = Some(name.into());
+        self
+    }
+
+    /// Iterate over the diagnostic options that are in effect.
+    fn diagnostics(&self) -> impl Iterator<Item = &'static str> + '_ {
+        let defaults = DEFAULT_DIAGNOSTICS.load(Ordering::Relaxed);
+        DIAGNOSTICS.iter().enumerate().filter(move |&(i, _)| {
+            self.diagnostics[i].unwrap_or(defaults & (1 << i) != 0)
+        }).map(|(_, &key)| key)
+    }
+
+    /// Prepare the options for a driver, mapping names to its own keys.
+    ///
+    /// Fails with `InvalidOption` for a rejected option, or for the first key the driver does
+    /// not list.
+    fn resolve(&self, driver: &Driver) -> AoResult<DriverOptions> {
+        if let Some((ref key, ref value)) = self.rejected {
+            return Err(AoError::InvalidOption(key.clone(), value.clone()));
+        }
+        let accepted: Vec<&str> = match driver.get_info() {
+            Some(info) => info.options().collect(),
+            None => return Err(AoError::NoDriver)
+        };
+        for (key, value) in self.iter() {
+            if !COMMON_OPTIONS.contains(&key) && !accepted.contains(&key) {
+                return Err(AoError::InvalidOption(key.to_owned(), value.to_owned()));
+            }
+        }
+
+        let merged = match *default_options() {
+            Some(ref defaults) => self.with_defaults(defaults, &accepted),
+            None => self.clone()
+        };
+        let mut resolved = DriverOptions {
+            client_name: None,
+            stream_name: None,
+            ..merged.clone()
+        };
+        let names = [(&merged.client_name, &CLIENT_NAME_KEYS), (&merged.stream_name, &STREAM_NAME_KEYS)];
+        for &(name, keys) in names.iter() {
+            let key = keys.iter().find(|k| accepted.contains(k));
+            if let (Some(name), Some(key)) = (name.as_ref(), key) {
+                if resolved.get(key).is_none() {
+                    resolved = resolved.set(*key, &name[..]);
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Fill in options not set here from `defaults`, skipping keys the driver
+    /// does not accept.
+    fn with_defaults(&self, defaults: &DriverOptions, accepted: &[&str]) -> DriverOptions {
+        let mut merged = self.clone();
+        for (key, value) in defaults.iter() {
+            let usable = COMMON_OPTIONS.contains(&key) || accepted.contains(&key);
+            if usable && merged.get(key).is_none() {
+                merged = merged.set(key, value);
+            }
+        }
+        for (option, default) in merged.diagnostics.iter_mut().zip(defaults.diagnostics.iter()) {
+            *option = option.or(*default);
+        }
+        merged.client_name = merged.client_name.or_else(|| defaults.client_name.clone());
+        merged.stream_name = merged.stream_name.or_else(|| defaults.stream_name.clone());
+        merged
+    }
+
+    /// Identify the option responsible for a `BadOption` error, if possible.
+    ///
+    /// libao does not say which option it rejected, so this can only tell
+    /// when a single driver-specific option was given.
+    fn attribute(&self, error: AoError) -> AoError {
+        if error != AoError::BadOption {
+            return error;
+        }
+        let mut specific = self.iter().filter(|&(key, _)| !COMMON_OPTIONS.contains(&key));
+        match (specific.next(), specific.next()) {
+            (Some((key, value)), None) => AoError::InvalidOption(key.to_owned(), value.to_owned()),
+            _ => error
+        }
+    }
+
+    /// Build the native option list and pass it to `f`.
+    ///
+    /// Fails with `InvalidString` if a key or value contains a nul byte.
+    fn with_native<F, U>(&self, f: F) -> AoResult<U>
+            where F: FnOnce(*const ffi::ao_option) -> U {
+        let mut list: *mut ffi::ao_option = ptr::null_mut();
+        let mut result = Ok(());
+        let diagnostics = self.diagnostics().map(|key| (key, "1"));
+        for (key, value) in self.iter().chain(diagnostics) {
+            let (key, value) = match (CString::new(key), CString::new(value)) {
+                (Ok(k), Ok(v)) => (k, v),
+                (Err(_), _) => {
+                    result = Err(AoError::InvalidString(key.to_owned()));
+                    break;
+                }
+                (_, Err(_)) => {
+                    result = Err(AoError::InvalidString(value.to_owned()));
+                    break;
+                }
+            };
+            // ao_append_option copies its arguments.
+            if unsafe { ffi::ao_append_option(&mut list, key.as_ptr(), value.as_ptr()) } == 0 {
+                result = Err(AoError::Unknown);
+                break;
+            }
+        }
+
+        let output = result.map(|()| f(list));
+        unsafe {
+            ffi::ao_free_options(list);
+        }
+        output
+    }
+}
+
+/// What a driver can play, as reported by `Driver::capabilities`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Sample widths in bits the driver accepts.
+    pub bits: Vec<usize>,
+    /// Largest number of channels the driver accepts, if limited and known.
+    pub max_channels: Option<usize>,
+    /// Whether the driver maps channels according to a `SampleFormat`
+    /// matrix. Drivers that do not ignore the matrix and play channels in
+    /// the order given.
+    pub matrix: bool,
+    /// Byte order the driver works in natively, if it has a preference.
+    /// Samples in any other order are swapped by libao before playback.
+    pub preferred_byte_order: Option<Endianness>,
+}
+
+impl Capabilities {
+    /// Whether a format is within these capabilities.
+    pub fn supports<T: Sample, F>(&self, format: &SampleFormat<T, F>) -> bool {
+        self.bits.contains(&format.bits()) &&
+            self.max_channels.is_none_or(|max| format.channels <= max)
+    }
+}
+
+/// Capabilities of the standard drivers, as (bits, channel limit, matrix).
+///
+/// These describe what libao's drivers accept in a typical build; the
+/// hardware behind a live driver may support less.
+fn known_capabilities(driver: Option<KnownDriver>) -> (&'static [usize], Option<usize>, bool) {
+    const ALL: &[usize] = &[8, 16, 24, 32];
+    const LOW: &[usize] = &[8, 16];
+    match driver {
+        Some(KnownDriver::Pulse) => (ALL, Some(32), true),
+        Some(KnownDriver::Alsa) => (ALL, None, true),
+        Some(KnownDriver::Oss) => (LOW, None, true),
+        Some(KnownDriver::Esd) => (LOW, Some(2), false),
+        Some(KnownDriver::Arts) => (LOW, Some(2), false),
+        Some(KnownDriver::Nas) => (LOW, Some(2), false),
+        Some(KnownDriver::Sndio) => (ALL, None, false),
+        Some(KnownDriver::Roar) => (ALL, None, false),
+        Some(KnownDriver::MacOsX) => (ALL, None, true),
+        Some(KnownDriver::Wmm) => (ALL, None, true),
+        Some(KnownDriver::Sun) => (LOW, Some(2), false),
+        Some(KnownDriver::Null) => (ALL, None, false),
+        Some(KnownDriver::Wav) => (ALL, Some(u16::MAX as usize), true),
+        Some(KnownDriver::Au) => (ALL, None, false),
+        Some(KnownDriver::Raw) => (ALL, None, false),
+        // Assume little of drivers we know nothing about.
+        None => (LOW, None, false),
+    }
+}
+
+/// An output driver.
+///
+/// This is an opaque handle. It borrows the `AO` it was obtained from, so it
+/// cannot be used once the library has been shut down:
+///
+/// ```compile_fail,E0597
+/// let driver;
+/// {
+///     let lib = ao::AO::init();
+///     driver = lib.get_driver("").unwrap();
+/// }
+/// driver.get_info();
+/// ```
+pub struct Driver<'a> {
+    id: c_int,
+    marker: PhantomData<&'a ()>
+}
+
+impl<'a> Driver<'a> {
+    /// Get the `DriverInfo` corresponding to this `Driver`.
+    pub fn get_info(& self) -> Option<DriverInfo<'a>> {
+        let id = self.id;
+
+        /// Turn a non-null C string pointer into static string slice.
+        ///
+        /// Panics if the string is not valid UTF-8.
+        unsafe fn sstr<'z>(s: *const c_char) -> &'z str {
+            str::from_utf8(CStr::from_ptr(s).to_bytes()).unwrap()
+        }
+
+        unsafe {
+            let info = ffi::ao_driver_info(id);
+            if info.is_null() {
+                None
+            } else {
+                let info = &*info;
+                Some(DriverInfo {
+                    name: sstr(info.name),
+                    short_name: sstr(info.short_name),
+                    comment: if info.comment.is_null() {
+                        None
+                    } else {
+                        Some(sstr(info.comment))
+                    },
+                    flavor: DriverType::from_c_int(info.flavor),
+                    priority: info.priority,
+                    id,
+                })
+            }
+        }
+    }
+
+    /// Describe what the driver can play, without opening a device.
+    ///
+    /// The result comes from a table of libao's standard drivers combined
+    /// with what the driver reports about itself, so it is cheap enough to
+    /// consult when choosing a format. Use `probe_capabilities` to test a
+    /// live driver against the actual hardware.
+    pub fn capabilities(&self) -> Capabilities {
+        let info = self.get_info();
+        let known = info.and_then(|i| KnownDriver::from_short_name(i.short_name));
+        let (bits, max_channels, matrix) = known_capabilities(known);
+        let preferred = unsafe {
+            let info = ffi::ao_driver_info(self.id);
+            if info.is_null() { 0 } else { (*info).preferred_byte_format }
+        };
+
+        Capabilities {
+            bits: bits.to_vec(),
+            max_channels,
+            // A driver listing the option handles it, whatever the table says.
+            matrix: matrix || info.is_some_and(|i| i.options().any(|o| o == "matrix")),
+            preferred_byte_order: match preferred {
+                ffi::AO_FMT_LITTLE => Some(Endianness::Little),
+                ffi::AO_FMT_BIG => Some(Endianness::Big),
+                ffi::AO_FMT_NATIVE => Some(Endianness::Native),
+                _ => None
+            },
+        }
+    }
+
+    /// Find what a live driver can play by opening devices with it.
+    ///
+    /// Tries 8, 16, 24 and 32 bit samples in stereo, then up to 8 channels,
+    /// at `sample_rate`, closing each device immediately. This opens as many
+    /// as 12 devices, which may take a noticeable time with some drivers, so
+    /// keep the result rather than probing repeatedly. Channel counts beyond
+    /// those probed are taken from `capabilities`, and `max_channels` is
+    /// `None` if not even a mono device would open. File drivers are not
+    /// probed, since opening them creates files.
+    pub fn probe_capabilities(&self, sample_rate: usize) -> Capabilities {
+        // Only the declared width reaches libao, so one sample type serves for
+        // every width, including 24 bits, which no `Sample` can carry.
+        fn opens(driver: &Driver, sample_rate: usize, bits: usize, channels: usize) -> bool {
+            let mut format = SampleFormat::<i16, &str>::new(sample_rate, channels,
+                                                            Endianness::Native, None);
+            format.bits = Some(bits);
+            let options = match DriverOptions::new().resolve(driver) {
+                Ok(o) => o,
+                Err(_) => return false
+            };
+            match options.with_native(|o| format.with_native(|f| unsafe {
+                ffi::ao_open_live(driver.id, f, o)
+            })) {
+                Ok(Ok(handle)) if !handle.is_null() => {
+                    unsafe {
+                        ffi::ao_close(handle);
+                    }
+                    true
+                }
+                _ => false
+            }
+        }
+
+        let mut caps = self.capabilities();
+        match self.get_info() {
+            Some(ref info) if info.flavor == DriverType::Live => (),
+            _ => return caps
+        }
+
+        let probed: Vec<(usize, bool)> = [8, 16, 24, 32].iter()
+            .map(|&bits| (bits, opens(self, sample_rate, bits, 2)))
+            .collect();
+        caps.bits = probed.iter().filter(|&&(_, ok)| ok).map(|&(bits, _)| bits).collect();
+
+        let mut channels = 0;
+        while channels < 8 && opens(self, sample_rate, 16, channels + 1) {
+            channels += 1;
+        }
+        caps.max_channels = match channels {
+            0 => None,
+            8 => caps.max_channels,
+            n => Some(n),
+        };
+        caps
+    }
+
+    /// Open a live output device.
+    ///
+    /// Returns `NotLive` if the specified driver is not a live output driver.
+    /// In this case, open the device as a file output instead.
+    pub fn open_live<T: Sample, S: AsRef<str>>(&self,
+            format: &SampleFormat<T, S>) -> AoResult<Device<'a, T>> {
+        self.open_live_with(format, &DriverOptions::new())
+    }
+
+    /// Open a live output device with driver-specific options.
+    pub fn open_live_with<T: Sample, S: AsRef<str>>(&self,
+            format: &SampleFormat<T, S>,
+            options: &DriverOptions) -> AoResult<Device<'a, T>> {
+        format.check_matrix()?;
+        format.check_bits()?;
+        let options = options.resolve(self)?;
+        let handle = options.with_native(|o| format.with_native(|f| unsafe {
+            ffi::ao_open_live(self.id, f, o)
+        }))??;
+
+        Device::<'a, T>::init(handle, format)
+            .map_err(|e| options.attribute(e).caused_by(|| self.device_error(&options)))
+    }
+
+    /// Find why the device node this driver opens cannot be opened, if it opens one.
+    fn device_error(&self, options: &DriverOptions) -> Option<io::Error> {
+        let name = self.get_info()?.short_name;
+        let &(_, key, default) = DEVICE_NODES.iter().find(|node| node.0 == name)?;
+        check_device_node(Path::new(options.get(key).unwrap_or(default))).err()
+    }
+
+    /// Open a file output device.
+    ///
+    /// `path` specifies the file to write to, and `overwrite` will
+    /// automatically replace any existing file if `true`.
+    ///
+    /// Returns `NotFile` if the requested driver is not a file output driver,
+    /// or `InvalidString` if `file` contains a nul byte.
     pub fn open_file<T: Sample, S: AsRef<str>>(&self,
             format: &SampleFormat<T, S>, file: &Path,
             overwrite: bool) -> AoResult<Device<'a, T>> {
+        self.open_file_with(format, file, overwrite, &DriverOptions::new())
+    }
 
+    /// Open a file output device with driver-specific options.
+    pub fn open_file_with<T: Sample, S: AsRef<str>>(&self,
+            format: &SampleFormat<T, S>, file: &Path, overwrite: bool,
+            options: &DriverOptions) -> AoResult<Device<'a, T>> {
+        format.check_matrix()?;
+        format.check_bits()?;
+        let options = options.resolve(self)?;
         let c_path = match file.to_str() {
             Some(s) => match CString::new(s) {
                 Ok(s) => s,
-                Err(_) => return Err(AoError::OpenFile)
+                Err(_) => return Err(AoError::InvalidString(s.to_owned()))
             },
             None => return Err(AoError::OpenFile)
         };
-        let handle = format.with_native(|f| {
+        let handle = options.with_native(|o| format.with_native(|f| {
             unsafe {
-                ffi::ao_open_file(self.id, c_path.as_ptr(), overwrite as c_int, f, ptr::null())
+                ffi::ao_open_file(self.id, c_path.as_ptr(), overwrite as c_int, f, o)
             }
+        }))??;
+
+        Device::<'a, T>::init(handle, format)
+            .map_err(|e| options.attribute(e).caused_by(|| file_error(file)))
+    }
+}
+
+impl Driver<'static> {
+    /// Open a live output device, giving up if it takes longer than `timeout`.
+    ///
+    /// Some drivers can block indefinitely while opening, such as `pulse`
+    /// with an unreachable network server or `oss` with a stuck device. The
+    /// open is made on a helper thread; if it has not finished in time this
+    /// fails with `Timeout`, and the helper thread is left to finish, closing
+    /// the device if it eventually opens. The thread may outlive the call, so
+    /// this requires a driver from an `AO` that is never dropped.
+    pub fn open_live_timeout<T, S>(&self, format: &SampleFormat<T, S>, options: &DriverOptions,
+                                   timeout: Duration) -> AoResult<Device<'static, T>>
+            where T: Sample + Send + 'static, S: AsRef<str> {
+        let driver = Driver { id: self.id, marker: PhantomData };
+        let mut owned = SampleFormat::<T, String>::new(format.sample_rate, format.channels,
+            format.byte_order, format.matrix.as_ref().map(|m| m.as_ref().to_owned()));
+        owned.bits = format.bits;
+        let options = options.clone();
+
+        let (opened, result) = mpsc::channel();
+        thread::spawn(move || {
+            let device = driver.open_live_with(&owned, &options).map(SendDevice);
+            // If the caller gave up waiting, the device is closed here.
+            let _ = opened.send(device);
         });
+        match result.recv_timeout(timeout) {
+            Ok(device) => device.map(|d| d.0),
+            Err(_) => Err(AoError::Timeout)
+        }
+    }
+}
+
+/// Moves a device to another thread.
+///
+/// libao device handles may be used from any thread so long as only one uses
+/// them at a time, which holds because the device is never touched again by
+/// the thread that sends it.
+struct SendDevice<S>(Device<'static, S>);
+
+unsafe impl<S: Send> Send for SendDevice<S> {}
+
+/// Live drivers that open a device node: the driver, the option naming the node, and its default.
+const DEVICE_NODES: [(&str, &str, &str); 2] = [
+    ("oss", "dsp", "/dev/dsp"),
+    ("sun", "dev", "/dev/audio"),
+];
 
-        Device::<'a, T>::init(handle)
+/// Open a device node for writing and close it again, without blocking.
+fn check_device_node(path: &Path) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NONBLOCK);
     }
+    options.open(path).map(|_| ())
+}
+
+/// Find why `file` cannot be opened for writing, without creating or changing it.
+fn file_error(file: &Path) -> Option<io::Error> {
+    let access = |path: &Path, mode| {
+        let path = CString::new(path.to_str()?).ok()?;
+        match unsafe { libc::access(path.as_ptr(), mode) } {
+            0 => None,
+            _ => Some(io::Error::last_os_error())
+        }
+    };
+    match access(file, libc::W_OK) {
+        // A new file needs a directory it can be created in.
+        Some(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let dir = match file.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new(".")
+            };
+            access(dir, libc::W_OK | libc::X_OK)
+        }
+        error => error
+    }
+}
+
+/// Amount of audio written to a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Number of frames (one sample for every channel) written.
+    pub frames: u64,
+    /// Duration of the audio written, at the device's sample rate.
+    pub time: Duration,
+    /// When the most recent write completed, or when the device was opened
+    /// if nothing has been written.
+    ///
+    /// Pairs `time` with the monotonic clock, so the stream position at a
+    /// later instant can be estimated while playback continues.
+    pub written_at: Instant,
+}
+
+impl Position {
+    fn new(frames: u64, sample_rate: usize, written_at: Instant) -> Position {
+        let rate = sample_rate.max(1) as u64;
+        Position {
+            frames,
+            time: Duration::from_secs(frames / rate) +
+                Duration::from_nanos((frames % rate) * 1_000_000_000 / rate),
+            written_at,
+        }
+    }
+}
+
+/// OS errors after which a write may succeed if tried again.
+const TRANSIENT_ERRORS: [c_int; 2] = [libc::EINTR, libc::EAGAIN];
+
+/// Times a write is retried after a transient error, unless changed with
+/// `Device::set_play_retries`.
+const DEFAULT_PLAY_RETRIES: u32 = 3;
+
+/// Wait before the first retry of a write refused because the device was busy,
+/// doubled for each retry after.
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+/// Counts of the writes made to a device, for monitoring.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceStats {
+    /// Bytes accepted by the driver.
+    pub bytes: u64,
+    /// Frames accepted by the driver, as counted by `Device::position`.
+    pub frames: u64,
+    /// Calls made to libao to play samples, whether or not they succeeded.
+    ///
+    /// Writes retried after a transient error are counted every time.
+    pub writes: u64,
+    /// Writes the driver failed, including those later retried successfully.
+    pub failures: u64,
 }
 
 /// An output device.
+///
+/// Like a `Driver`, a device cannot outlive the `AO` it was opened from:
+///
+/// ```compile_fail,E0597
+/// use ao::SampleFormat;
+/// use ao::Endianness::Native;
+///
+/// let device;
+/// {
+///     let lib = ao::AO::init();
+///     let format = SampleFormat::<i16, &'static str>::new(44100, 1, Native, None);
+///     device = lib.get_driver("").unwrap().open_live(&format).unwrap();
+/// }
+/// device.play(&[0]).unwrap();
+/// ```
+///
+/// It accepts only samples of the type it was opened with:
+///
+/// ```compile_fail,E0308
+/// use ao::SampleFormat;
+/// use ao::Endianness::Native;
+///
+/// let lib = ao::AO::init();
+/// let format = SampleFormat::<i16, &'static str>::new(44100, 1, Native, None);
+/// let device = lib.get_driver("").unwrap().open_live(&format).unwrap();
+/// device.play(&[0i32]).unwrap();
+/// ```
 pub struct Device<'a, S> {
     id: *mut ffi::ao_device,
+    sample_rate: usize,
+    channels: usize,
+    frames: Cell<u64>,
+    written_at: Cell<Instant>,
+    stats: Cell<DeviceStats>,
+    /// Times a write is retried after a transient error.
+    retries: u32,
+    /// Whether samples are in the opposite byte order to this machine's, and
+    /// must be swapped around any arithmetic on them.
+    swap: bool,
+    volume: f32,
+    muted: bool,
+    dither: bool,
+    /// State of the dither noise generator.
+    noise: Cell<u32>,
+    /// Samples with volume applied, kept to avoid reallocating.
+    scaled: RefCell<Vec<S>>,
+    /// Length of the fades applied after opening and before closing, in
+    /// frames, or zero for none.
+    declick: usize,
+    /// The last frame written, from which to fade out on close.
+    last_frame: RefCell<Vec<S>>,
+    /// Builds the fade out, since `Drop` cannot require `S: Sample`.
+    fade_out: fn(&[S], usize, bool) -> Vec<S>,
+    /// Callbacks registered with `at_frame`, by frame.
+    cues: RefCell<Cues<'a>>,
+    /// Receives a copy of everything written, if a tap has been made.
+    tap: Option<SampleTap<S>>,
     m0: PhantomData<&'a ()>,
     m1: PhantomData<S>
 }
@@ -421,18 +1717,187 @@ pub struct Device<'a, S> {
 impl<'a, S: Sample> Device<'a, S> {
 
     /// Inner helper to finish Device init given a FFI handle.
-    fn init(handle: *mut ffi::ao_device) -> AoResult<Device<'a, S>> {
+    fn init<F>(handle: *mut ffi::ao_device,
+               format: &SampleFormat<S, F>) -> AoResult<Device<'a, S>> {
         if handle.is_null() {
             Err(AoError::from_errno())
         } else {
             Ok(Device {
                 id: handle,
+                sample_rate: format.sample_rate,
+                channels: format.channels,
+                frames: Cell::new(0),
+                stats: Cell::new(DeviceStats::default()),
+                retries: DEFAULT_PLAY_RETRIES,
+                swap: format.byte_order.is_foreign(),
+                written_at: Cell::new(Instant::now()),
+                volume: 1.0,
+                muted: false,
+                dither: false,
+                noise: Cell::new(0x9e37_79b9),
+                scaled: RefCell::new(Vec::new()),
+                declick: 0,
+                last_frame: RefCell::new(Vec::new()),
+                fade_out: fade_out::<S>,
+                cues: RefCell::new(BTreeMap::new()),
+                tap: None,
                 m0: PhantomData,
                 m1: PhantomData
             })
         }
     }
 
+    /// Set how many times a write is retried after a transient error, such as
+    /// being interrupted by a signal. The default is 3; `0` disables retries.
+    ///
+    /// A write interrupted by a signal (`EINTR`) is retried at once. One
+    /// refused because the device was busy (`EAGAIN`) is retried after 1ms,
+    /// doubling for each retry after, so the default waits at most 7ms in all.
+    ///
+    /// libao reports only whether a write succeeded, not how much of it the
+    /// driver accepted. Drivers loop over short writes themselves, so a write
+    /// may fail after part of the buffer has already been played. A retry can
+    /// only send the whole buffer again, which may repeat that part; disable
+    /// retries where a brief repeat is worse than a dropout.
+    pub fn set_play_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Set the linear gain applied to samples in `play`.
+    ///
+    /// `1.0` plays samples unmodified. Negative values are treated as `0.0`.
+    /// Samples that would exceed the range of the sample type are clipped.
+    /// Samples in a byte order other than the machine's are read in the
+    /// device's byte order, scaled, and written back in it.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.max(0.0);
+    }
+
+    /// Get the linear gain applied to samples in `play`.
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Play silence in place of any samples, until `unmute` is called.
+    ///
+    /// The device continues to receive the same amount of data, so playback
+    /// timing is unaffected.
+    pub fn mute(&mut self) {
+        self.muted = true;
+    }
+
+    /// Resume playing samples after `mute`.
+    pub fn unmute(&mut self) {
+        self.muted = false;
+    }
+
+    /// Whether the device is muted.
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Enable or disable dither when applying volume.
+    ///
+    /// Scaling samples and rounding them back to integers introduces
+    /// distortion correlated with the signal, which is most audible in quiet
+    /// passages at low bit depths. Dithering adds one least significant bit
+    /// of triangular noise before rounding to decorrelate it.
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    /// Fade in over `ramp` after the device opens, and out over `ramp` when it
+    /// closes.
+    ///
+    /// Many live outputs pop when a stream starts or stops abruptly. With a
+    /// ramp of a few milliseconds, the first samples played are faded in from
+    /// silence, and when the device is dropped the last frame played is faded
+    /// out to silence. Only the part of the fade in not yet played is
+    /// applied. `Duration::ZERO`, the default, disables both.
+    pub fn set_declick(&mut self, ramp: Duration) {
+        self.declick = frames_in(ramp, self.sample_rate) as usize;
+    }
+
+    /// Whether the fade in after opening is still being played.
+    fn fading_in(&self) -> bool {
+        self.frames.get() < self.declick as u64
+    }
+
+    /// Apply the fade in to `samples`, which are about to be written.
+    fn fade_in(&self, samples: &mut [S]) {
+        let per_frame = match samples.first() {
+            Some(s) => (self.channels / s.channels()).max(1),
+            None => return
+        };
+        let start = self.frames.get() as usize;
+        let fading = (self.declick.saturating_sub(start) * per_frame).min(samples.len());
+        let samples = &mut samples[..fading];
+        if self.swap {
+            swap_samples(samples);
+        }
+        for (i, frame) in samples.chunks_mut(per_frame).enumerate() {
+            let gain = (start + i) as f32 / self.declick as f32;
+            for s in frame.iter_mut() {
+                *s = s.scale(gain);
+            }
+        }
+        if self.swap {
+            swap_samples(samples);
+        }
+    }
+
+    /// Generate triangular noise in the range (-1, 1).
+    fn next_noise(&self) -> f32 {
+        // xorshift32: quality is unimportant, speed is.
+        let next = || {
+            let mut x = self.noise.get();
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            self.noise.set(x);
+            x as f32 / u32::MAX as f32
+        };
+        next() - next()
+    }
+
+    /// Get the amount of audio played through this device so far.
+    ///
+    /// This counts what has been handed to libao, which runs ahead of what
+    /// is audible by the output's latency.
+    pub fn position(&self) -> Position {
+        Position::new(self.frames.get(), self.sample_rate, self.written_at.get())
+    }
+
+    /// Call `callback` once `frame` frames have been played through this
+    /// device.
+    ///
+    /// Callbacks run on the thread calling `play`, after the write that
+    /// crosses `frame` returns, so they fire as early as libao accepts the
+    /// audio rather than when it is heard; see `position`. Several callbacks
+    /// may be registered for the same frame, and run in the order registered.
+    /// A callback for a frame already played runs after the next write.
+    pub fn at_frame<F>(&mut self, frame: u64, callback: F) where F: FnOnce() + Send + 'a {
+        self.cues.get_mut().entry(frame).or_default().push(Box::new(callback));
+    }
+
+    /// Keep the last `frames` frames played, for reading from another thread.
+    ///
+    /// The samples are recorded as written to libao, with volume and fades
+    /// applied. Making a new tap disconnects any made before.
+    pub fn tap(&mut self, frames: usize) -> SampleTap<S> {
+        let tap = SampleTap::new(frames, self.channels, S::silence().channels());
+        self.tap = Some(tap.clone());
+        tap
+    }
+
+    /// Get counts of the writes made to this device since it was opened.
+    pub fn stats(&self) -> DeviceStats {
+        DeviceStats {
+            frames: self.frames.get(),
+            ..self.stats.get()
+        }
+    }
+
     /// Plays packed samples through a device.
     ///
     /// For multi-channel output, channels are interleaved, such that positions
@@ -449,54 +1914,359 @@ impl<'a, S: Sample> Device<'a, S> {
     /// ```ignore
     /// my_device.play(&[[0, 0, 0, 0], [0, 0, 0, 0]]);
     /// ```
-    pub fn play(&self, samples: &[S]) {
-        unsafe {
-            let len = samples.len() * size_of::<S>();
-            ffi::ao_play(self.id, samples.as_ptr() as *const i8, len as u32);
+    ///
+    /// A write interrupted by a signal, or refused because the device was
+    /// momentarily busy, is retried (see `set_play_retries`).
+    ///
+    /// Returns `Play` if the driver fails to accept the samples, wrapped in a
+    /// `System` error with the OS error if the driver left one in `errno`.
+    /// The device should then be closed, unless the OS error is a transient
+    /// one that retrying did not clear, in which case playing may be tried
+    /// again later.
+    pub fn play(&self, samples: &[S]) -> AoResult<()> {
+        if self.muted || self.volume != 1.0 || self.fading_in() {
+            let mut scaled = self.scaled.borrow_mut();
+            scaled.clear();
+            self.extend_scaled(&mut scaled, samples);
+            if self.fading_in() {
+                self.fade_in(&mut scaled);
+            }
+            return self.write(&scaled);
+        }
+        self.write(samples)
+    }
+
+    /// Play several slices of samples back to back, as if concatenated.
+    ///
+    /// The slices are gathered into a buffer kept by the device and written
+    /// at once, so audio split across a decoder's packet buffers can be
+    /// played without allocating. Slices need not hold whole frames, as long
+    /// as together they do.
+    pub fn play_vectored(&self, slices: &[&[S]]) -> AoResult<()> {
+        if let [samples] = slices {
+            return self.play(samples);
+        }
+        let mut gathered = self.scaled.borrow_mut();
+        gathered.clear();
+        for samples in slices {
+            if self.muted || self.volume != 1.0 {
+                self.extend_scaled(&mut gathered, samples);
+            } else {
+                gathered.extend_from_slice(samples);
+            }
+        }
+        if self.fading_in() {
+            self.fade_in(&mut gathered);
+        }
+        self.write(&gathered)
+    }
+
+    /// Append `samples` to `out` with volume applied.
+    fn extend_scaled(&self, out: &mut Vec<S>, samples: &[S]) {
+        if self.muted {
+            out.resize(out.len() + samples.len(), S::silence());
+        } else if self.swap || self.dither {
+            let start = out.len();
+            out.extend_from_slice(samples);
+            let added = &mut out[start..];
+            if self.swap {
+                swap_samples(added);
+            }
+            if self.dither {
+                let mut noise = || self.next_noise();
+                for s in added.iter_mut() {
+                    *s = s.scale_dithered(self.volume, &mut noise);
+                }
+            } else {
+                for s in added.iter_mut() {
+                    *s = s.scale(self.volume);
+                }
+            }
+            if self.swap {
+                swap_samples(added);
+            }
+        } else {
+            S::scale_into(samples, self.volume, out);
+        }
+    }
+
+    /// Play buffers from `source` until `duration` of audio has been played.
+    ///
+    /// The buffer that crosses `duration` is cut short at the last whole
+    /// frame within it, and no more are taken from `source`. Since `play`
+    /// blocks while the device is busy, this returns about `duration` after
+    /// it is called, less the output's latency.
+    ///
+    /// Returns the number of frames played, which is less than `duration`
+    /// covers if `source` ran out first.
+    pub fn play_for<I>(&self, source: I, duration: Duration) -> AoResult<u64>
+            where I: IntoIterator<Item = Vec<S>> {
+        let start = self.frames.get();
+        let frames = frames_in(duration, self.sample_rate);
+        for samples in TakeFrames::new(source.into_iter(), frames, self.channels) {
+            self.play(&samples)?;
+        }
+        Ok(self.frames.get() - start)
+    }
+
+    /// Play raw samples from `reader` until it reaches the end.
+    ///
+    /// Samples are read in chunks of about `chunk_bytes`, rounded down to
+    /// whole frames, in the layout `play` expects. If `swap` is true the bytes
+    /// of each sample are reversed first, for input in the opposite byte
+    /// order to the device's. `progress` is called after each chunk with the
+    /// number of frames played so far.
+    ///
+    /// Returns the number of frames played. Fails with `InvalidData` if the
+    /// input ends partway through a frame, after playing the whole frames
+    /// before it; playback errors are returned as `io::Error`s wrapping the
+    /// `AoError`.
+    pub fn play_from_reader<R, P>(&self, mut reader: R, chunk_bytes: usize, swap: bool,
+                                  mut progress: P) -> io::Result<u64>
+            where R: Read, P: FnMut(u64) {
+        let per_value = S::silence().channels();
+        let sample_bytes = size_of::<S>() / per_value;
+        let frame_bytes = sample_bytes * self.channels.max(1);
+        let frames = (chunk_bytes / frame_bytes).max(1);
+        let mut buffer = vec![S::silence(); frames * self.channels.max(1) / per_value];
+
+        let mut played = 0;
+        loop {
+            // Sample is sealed to integers and arrays of them, which are valid
+            // for any bytes, so the buffer can be read into directly.
+            let bytes = unsafe {
+                slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, size_of_val(&buffer[..]))
+            };
+            let mut filled = 0;
+            while filled < bytes.len() {
+                match reader.read(&mut bytes[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e)
+                }
+            }
+            let whole = filled / frame_bytes * frame_bytes;
+            if swap {
+                simd::swap_bytes(&mut bytes[..whole], sample_bytes);
+            }
+
+            if whole > 0 {
+                let values = whole / frame_bytes * self.channels / per_value;
+                self.play(&buffer[..values]).map_err(io::Error::other)?;
+                played += (whole / frame_bytes) as u64;
+                progress(played);
+            }
+            if filled < buffer.len() * size_of::<S>() {
+                if filled != whole {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "input ended partway through a frame"));
+                }
+                return Ok(played);
+            }
+        }
+    }
+
+    fn write(&self, samples: &[S]) -> AoResult<()> {
+        let len = size_of_val(samples);
+        let mut stats = self.stats.get();
+        let mut retries = 0;
+        loop {
+            ffi::clear_errno();
+            let ok = unsafe {
+                ffi::ao_play(self.id, samples.as_ptr() as *const i8, len as u32)
+            };
+            stats.writes += 1;
+            if ok != 0 {
+                break;
+            }
+            stats.failures += 1;
+
+            // ao_play is all or nothing to its caller, so a retry can only
+            // replay the whole buffer; see set_play_retries.
+            let code = ffi::errno();
+            if !TRANSIENT_ERRORS.contains(&code) || retries == self.retries {
+                self.stats.set(stats);
+                return Err(match code {
+                    0 => AoError::Play,
+                    code => AoError::System(Box::new(AoError::Play), OsError(code)),
+                });
+            }
+            // A busy device needs time to drain; retrying at once would only use up the retries.
+            if code == libc::EAGAIN {
+                thread::sleep(BUSY_RETRY_DELAY * (1 << retries.min(16)));
+            }
+            retries += 1;
+        }
+        stats.bytes += len as u64;
+        self.stats.set(stats);
+        if let Some(ref tap) = self.tap {
+            tap.push(samples);
+        }
+
+        if let Some(s) = samples.first() {
+            if self.declick > 0 {
+                let per_frame = (self.channels / s.channels()).max(1);
+                let mut last = self.last_frame.borrow_mut();
+                last.clear();
+                last.extend_from_slice(&samples[samples.len() - per_frame.min(samples.len())..]);
+            }
+            let frames = samples.len() * s.channels() / self.channels.max(1);
+            self.frames.set(self.frames.get() + frames as u64);
+            self.written_at.set(Instant::now());
+            // Taken out first so a callback can never find the cues borrowed.
+            let due = take_cues(&mut self.cues.borrow_mut(), self.frames.get());
+            fire_cues(due);
+        }
+        Ok(())
+    }
+}
+
+/// Callbacks to run when playback reaches a frame, by frame.
+type Cues<'a> = BTreeMap<u64, Vec<Box<dyn FnOnce() + Send + 'a>>>;
+
+/// Remove the cues at or before `position` from `cues`.
+fn take_cues<'a>(cues: &mut Cues<'a>, position: u64) -> Cues<'a> {
+    let later = cues.split_off(&position.saturating_add(1));
+    mem::replace(cues, later)
+}
+
+/// Run cues in order of frame, then of registration.
+fn fire_cues(cues: Cues) {
+    for callback in cues.into_values().flatten() {
+        callback();
+    }
+}
+
+/// Number of frames making up `duration` at `sample_rate`.
+fn frames_in(duration: Duration, sample_rate: usize) -> u64 {
+    (duration.as_nanos() * sample_rate as u128 / 1_000_000_000) as u64
+}
+
+/// Reverse the bytes of each sample in `samples`, between the device's byte
+/// order and this machine's.
+pub(crate) fn swap_samples<S: Sample>(samples: &mut [S]) {
+    let width = size_of::<S>() / S::silence().channels();
+    // Sample is sealed to integers and arrays of them, which are valid for
+    // any bytes.
+    let bytes = unsafe {
+        slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, size_of_val(samples))
+    };
+    simd::swap_bytes(bytes, width);
+}
+
+/// Ends a source of buffers after a number of frames.
+struct TakeFrames<I> {
+    inner: I,
+    remaining: u64,
+    channels: usize,
+}
+
+impl<I> TakeFrames<I> {
+    fn new(inner: I, frames: u64, channels: usize) -> TakeFrames<I> {
+        TakeFrames {
+            inner,
+            remaining: frames,
+            channels: channels.max(1),
+        }
+    }
+}
+
+impl<S: Sample, I: Iterator<Item = Vec<S>>> Iterator for TakeFrames<I> {
+    type Item = Vec<S>;
+
+    fn next(&mut self) -> Option<Vec<S>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut samples = self.inner.next()?;
+        let per_value = S::silence().channels();
+        let allowed = self.remaining * (self.channels / per_value).max(1) as u64;
+        if samples.len() as u64 > allowed {
+            samples.truncate(allowed as usize);
+        }
+        let frames = (samples.len() * per_value / self.channels) as u64;
+        self.remaining -= frames.min(self.remaining);
+        Some(samples)
+    }
+}
+
+impl<S: Sample + Send + 'static> Device<'static, S> {
+    /// Feed this device from a lock-free ring buffer.
+    ///
+    /// The device is moved to a new playback thread which plays samples as
+    /// they are written to the returned `StreamWriter`. `capacity` is the
+    /// size of the buffer in samples.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn streaming_writer(self, capacity: usize) -> stream::StreamWriter<S> {
+        stream::StreamWriter::new(self, capacity)
+    }
+}
+
+impl<'a, S> Device<'a, S> {
+    /// Close the device, reporting whether libao could do so cleanly.
+    ///
+    /// Dropping a device also closes it, but any error is only passed to the
+    /// handler set with `set_close_error_handler`. The fade out set with
+    /// `set_declick` is played first, as when dropped.
+    pub fn close(mut self) -> AoResult<()> {
+        self.close_handle()
+    }
+
+    fn close_handle(&mut self) -> AoResult<()> {
+        if self.id.is_null() {
+            return Ok(());
+        }
+        let last = self.last_frame.borrow();
+        if self.declick > 0 && !last.is_empty() {
+            let ramp = (self.fade_out)(&last, self.declick, self.swap);
+            unsafe {
+                // The device is closing, so a failure to play the fade is not reported.
+                ffi::ao_play(self.id, ramp.as_ptr() as *const i8, size_of_val(&ramp[..]) as u32);
+            }
+        }
+        ffi::clear_errno();
+        let ok = unsafe { ffi::ao_close(self.id) };
+        self.id = ptr::null_mut();
+        if ok != 0 {
+            return Ok(());
+        }
+        match ffi::errno() {
+            0 => Err(AoError::Close),
+            code => Err(AoError::System(Box::new(AoError::Close), OsError(code))),
         }
     }
 }
 
 impl<'a, S> Drop for Device<'a, S> {
     fn drop(&mut self) {
-        unsafe {
-            ffi::ao_close(self.id);
+        if let Err(e) = self.close_handle() {
+            // Not called under the lock, in case the handler drops devices itself.
+            let handler = close_error_handler().clone();
+            if let Some(handler) = handler {
+                handler(&e);
+            }
         }
     }
 }
 
-// Driver<'a> must not be able to outlive the &'a AO that created it.
-// Unfortunately there's no #[compile_fail] for #[test] like
-// #[should_fail].
-/*
-#[test]
-fn test_driver_lifetime() {
-    let driver: Driver;
-    {
-        let lib = AO::init();
-        driver = lib.get_driver("").unwrap();
+/// Frames fading `last` out to silence over `frames` frames, swapping bytes
+/// around the scaling if `swap` is set.
+fn fade_out<S: Sample>(last: &[S], frames: usize, swap: bool) -> Vec<S> {
+    let mut last = last.to_vec();
+    if swap {
+        swap_samples(&mut last);
     }
-    driver.get_info();
+    let mut ramp: Vec<S> = (1..=frames).flat_map(|n| {
+        let gain = 1.0 - n as f32 / frames as f32;
+        last.iter().map(move |s| s.scale(gain))
+    }).collect();
+    if swap {
+        swap_samples(&mut ramp);
+    }
+    ramp
 }
-*/
-
-// Device<S> must not accept samples of any type other than S.
-/*
-#[test]
-fn test_sample_variance() {
-    let lib = AO::init();
-    let device = lib.get_driver("").unwrap().open_live::<i16>(&SampleFormat {
-        sample_rate: 44100,
-        channels: 1,
-        byte_order: Native,
-        matrix: None
-    }).unwrap();
-    // Invalid: does not match declared sample format
-    device.play(&[0i32]);
-    // OK
-    device.play(&[0i16]);
-}
-*/
 
 /// Task fails on multiple initialization.
 #[test]
@@ -506,3 +2276,74 @@ fn test_multiple_instantiation() {
     let lib = AO::init();
     let lib2 = AO::init();
 }
+
+/// Known drivers round-trip through their short names.
+#[test]
+fn test_known_driver_names() {
+    for &d in KnownDriver::ALL.iter() {
+        assert_eq!(KnownDriver::from_short_name(d.short_name()), Some(d));
+    }
+    assert_eq!(KnownDriver::from_short_name("bogus"), None);
+}
+
+/// Scaling saturates at the limits of the sample type, and applies to every
+/// channel of a frame.
+#[test]
+fn test_sample_scale() {
+    assert_eq!(i16::MAX.scale(2.0), i16::MAX);
+    assert_eq!(i8::MIN.scale(1.5), i8::MIN);
+    assert_eq!([1000i16, -1000].scale(0.5), [500, -500]);
+    assert_eq!(3i16.scale_dithered(0.5, &mut || 0.4), 2);
+    assert_eq!(3i16.scale_dithered(0.5, &mut || -0.4), 1);
+}
+
+/// Sources are cut at the frame where the limit falls, whether samples are
+/// given one value per channel or one value per frame.
+#[test]
+fn test_take_frames() {
+    let source = vec![vec![1i16; 6], vec![2; 6], vec![3; 6]];
+    let taken: Vec<_> = TakeFrames::new(source.into_iter(), 4, 2).collect();
+    assert_eq!(taken, [vec![1; 6], vec![2; 2]]);
+
+    let frames = vec![vec![[0i16; 2]; 3]; 3];
+    let taken: Vec<_> = TakeFrames::new(frames.into_iter(), 4, 2).collect();
+    assert_eq!(taken.iter().map(|b| b.len()).collect::<Vec<_>>(), [3, 1]);
+
+    assert_eq!(frames_in(Duration::from_millis(1500), 44100), 66150);
+}
+
+/// Matrix entries are checked against libao's channel names.
+#[test]
+fn test_check_matrix() {
+    let format = |m| SampleFormat::<i16, &str>::new(44100, 2, Endianness::Native, Some(m));
+    assert_eq!(format("L,R").check_matrix(), Ok(()));
+    assert_eq!(format("L, R, C, LFE, BR, BL").check_matrix(), Ok(()));
+    assert_eq!(format("L,Rear").check_matrix(), Err(AoError::InvalidChannel("Rear".to_string())));
+    assert_eq!(format("l,r").check_matrix(), Err(AoError::InvalidChannel("l".to_string())));
+    assert_eq!(format("L,\0R").check_matrix(), Err(AoError::InvalidString("L,\0R".to_string())));
+}
+
+/// Driver types libao may add later are kept rather than rejected.
+#[test]
+fn test_driver_type_from_c_int() {
+    assert_eq!(DriverType::from_c_int(ffi::AO_TYPE_LIVE), DriverType::Live);
+    assert_eq!(DriverType::from_c_int(ffi::AO_TYPE_FILE), DriverType::File);
+    assert_eq!(DriverType::from_c_int(7), DriverType::Unknown(7));
+}
+
+/// Declared bits must fit the sample type, and frames declare the bits of
+/// one channel.
+#[test]
+fn test_check_bits() {
+    let mut format = SampleFormat::<i16, &str>::new(44100, 1, Endianness::Native, None);
+    assert_eq!(format.bits(), 16);
+    format.bits = Some(12);
+    assert_eq!((format.bits(), format.check_bits()), (12, Ok(())));
+    format.bits = Some(8);
+    assert_eq!(format.check_bits(), Err(AoError::BadFormat));
+
+    let mut wide = SampleFormat::<i32, &str>::new(44100, 1, Endianness::Native, None);
+    wide.bits = Some(24);
+    assert_eq!(wide.check_bits(), Err(AoError::BadFormat));
+    assert_eq!(SampleFormat::<[i16; 2], &str>::new(44100, 2, Endianness::Native, None).bits(), 16);
+}
@@ -0,0 +1,571 @@
+//! A stand-in for libao, for testing without it.
+//!
+//! With the `mock` feature enabled, the crate does not link to libao at all. Its calls are
+//! answered by an in-process fake, so code built on this crate can be tested on machines without
+//! libao or any audio hardware. The fake offers four drivers:
+//!
+//!  * `null`, the only live driver and the default;
+//!  * `wav`, `au` and `raw`, file drivers. Like libao's, they write samples in the byte order of
+//!    their file format, swapping them if necessary, and the `wav` and `au` drivers write a
+//!    header which is completed when the device is closed.
+//!
+//! Every device opened is recorded along with its format, options and the bytes played to it,
+//! and may be inspected with `devices` until discarded with `clear`. Failures can be injected
+//! with `fail_next_open`, `fail_next_play`, `interrupt_next_play` and `refuse_next_play`.
+//!
+//! Recorded state is shared by the whole process, so tests running in parallel should pick out
+//! their own devices, for example by sample rate or file name.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::Native;
+//! use ao::mock;
+//!
+//! fn main() {
+//!     let lib = AO::init();
+//!     let driver = lib.get_driver("null").unwrap();
+//!     let format = SampleFormat::<i16, &'static str>::new(8000, 1, Native, None);
+//!     driver.open_live(&format).unwrap().play(&[1, 2]).unwrap();
+//!
+//!     let device = mock::devices().into_iter().find(|d| d.sample_rate == 8000).unwrap();
+//!     assert_eq!(device.played.len(), 4);
+//!     assert!(device.closed);
+//! }
+//! ```
+
+use super::{simd, AoError, Endianness};
+use ffi::{self, ao_device, ao_info, ao_option, ao_sample_format};
+use libc::{self, c_char, c_int};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+/// A device opened through the fake libao.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockDevice {
+    /// Short name of the driver the device was opened with.
+    pub driver: String,
+    /// The file written, for file drivers.
+    pub path: Option<PathBuf>,
+    /// Bits per sample.
+    pub bits: usize,
+    /// Samples per second.
+    pub sample_rate: usize,
+    /// Samples per frame.
+    pub channels: usize,
+    /// Byte order samples were given in.
+    pub byte_order: Endianness,
+    /// The channel matrix, if one was given.
+    pub matrix: Option<String>,
+    /// Options passed when opening, in order.
+    pub options: Vec<(String, String)>,
+    /// Every byte played to the device.
+    pub played: Vec<u8>,
+    /// Whether the device has been closed.
+    pub closed: bool,
+}
+
+/// Get every device opened so far, in the order they were opened.
+pub fn devices() -> Vec<MockDevice> {
+    state().devices.values().map(|d| d.record.clone()).collect()
+}
+
+/// Discard the records of every closed device.
+///
+/// Long-running tests, such as fuzzers, should call this regularly, since records are otherwise
+/// kept for the life of the process.
+pub fn clear() {
+    state().devices.retain(|_, d| !d.record.closed);
+}
+
+/// Make the next attempt to open a device fail with `error`.
+///
+/// `InvalidOption` is reported as `BadOption`, `InvalidChannel` as `BadFormat`, `Timeout` as
+/// `OpenDevice`, and `Play`, `Close` and `InvalidString` as `Unknown`, as libao itself would. The
+/// cause of a `System` error is dropped, since libao reports none.
+pub fn fail_next_open(error: AoError) {
+    let code = match error {
+        AoError::NoDriver => ffi::AO_ENODRIVER,
+        AoError::NotFile => ffi::AO_ENOTFILE,
+        AoError::NotLive => ffi::AO_ENOTLIVE,
+        AoError::BadOption | AoError::InvalidOption(..) => ffi::AO_EBADOPTION,
+        AoError::OpenDevice | AoError::Timeout => ffi::AO_EOPENDEVICE,
+        AoError::OpenFile => ffi::AO_EOPENFILE,
+        AoError::FileExists => ffi::AO_EFILEEXISTS,
+        AoError::BadFormat | AoError::InvalidChannel(..) => ffi::AO_EBADFORMAT,
+        AoError::Unknown | AoError::Play | AoError::Close | AoError::InvalidString(..) => {
+            ffi::AO_EFAIL
+        }
+        AoError::System(error, _) => return fail_next_open(*error),
+    };
+    state().fail_open = Some(code);
+}
+
+/// Make the next write to any device fail.
+pub fn fail_next_play() {
+    state().fail_play = Some(libc::EIO);
+}
+
+/// Make the next write made by this thread fail as if interrupted by a signal.
+///
+/// `Device::play` retries such failures, so unlike `fail_next_play` this is only seen in the
+/// device's `stats`. It is limited to the calling thread so that tests running in parallel do
+/// not take each other's interruptions.
+pub fn interrupt_next_play() {
+    TRANSIENT.with(|t| t.set(Some(libc::EINTR)));
+}
+
+/// Make the next write made by this thread fail as if the device were too busy to accept it.
+///
+/// As with `interrupt_next_play`, `Device::play` retries the write, though only after a delay.
+pub fn refuse_next_play() {
+    TRANSIENT.with(|t| t.set(Some(libc::EAGAIN)));
+}
+
+/// Make the next device closed by this thread fail to close, as if its disk were full.
+///
+/// The device is still closed. Like `interrupt_next_play`, this is limited to the calling thread
+/// so that tests running in parallel do not close each other's devices with errors.
+pub fn fail_next_close() {
+    FAIL_CLOSE.with(|f| f.set(true));
+}
+
+struct OpenDevice {
+    record: MockDevice,
+    output: Option<FileOutput>,
+}
+
+/// Headers written by the file drivers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Header {
+    None,
+    Wav,
+    Au,
+}
+
+/// A file being written by a file driver.
+struct FileOutput {
+    file: File,
+    header: Header,
+    /// Whether each sample's bytes must be reversed for the file's byte order.
+    swap: bool,
+    /// Bytes in each sample.
+    width: usize,
+    /// Bytes of sample data written.
+    written: u32,
+}
+
+impl FileOutput {
+    fn write_header(&mut self, format: &MockDevice) -> io::Result<()> {
+        let (rate, channels, bits) = (format.sample_rate as u32, format.channels as u32,
+                                      format.bits as u32);
+        let width = self.width as u32;
+        let mut header = Vec::new();
+        match self.header {
+            Header::None => return Ok(()),
+            Header::Wav => {
+                header.extend_from_slice(b"RIFF");
+                header.extend_from_slice(&(36 + self.written).to_le_bytes());
+                header.extend_from_slice(b"WAVEfmt ");
+                header.extend_from_slice(&16u32.to_le_bytes());
+                // Uncompressed PCM.
+                header.extend_from_slice(&1u16.to_le_bytes());
+                header.extend_from_slice(&(channels as u16).to_le_bytes());
+                header.extend_from_slice(&rate.to_le_bytes());
+                header.extend_from_slice(&(rate * channels * width).to_le_bytes());
+                header.extend_from_slice(&((channels * width) as u16).to_le_bytes());
+                header.extend_from_slice(&(bits as u16).to_le_bytes());
+                header.extend_from_slice(b"data");
+                header.extend_from_slice(&self.written.to_le_bytes());
+            }
+            Header::Au => {
+                header.extend_from_slice(b".snd");
+                header.extend_from_slice(&24u32.to_be_bytes());
+                header.extend_from_slice(&self.written.to_be_bytes());
+                // Linear PCM encodings are numbered 2 to 5 from one to four bytes.
+                header.extend_from_slice(&(width + 1).to_be_bytes());
+                header.extend_from_slice(&rate.to_be_bytes());
+                header.extend_from_slice(&channels.to_be_bytes());
+            }
+        }
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.seek(SeekFrom::End(0)).map(|_| ())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.swap {
+            let mut swapped = bytes.to_vec();
+            simd::swap_bytes(&mut swapped, self.width);
+            self.file.write_all(&swapped)?;
+        } else {
+            self.file.write_all(bytes)?;
+        }
+        self.written = self.written.wrapping_add(bytes.len() as u32);
+        Ok(())
+    }
+}
+
+struct State {
+    /// Devices by handle.
+    devices: BTreeMap<usize, OpenDevice>,
+    /// Handle of the next device opened.
+    next_handle: usize,
+    fail_open: Option<c_int>,
+    /// Error code the next write fails with.
+    fail_play: Option<c_int>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    devices: BTreeMap::new(),
+    // Handles start from one, so none is null.
+    next_handle: 1,
+    fail_open: None,
+    fail_play: None,
+});
+
+thread_local! {
+    static ERRNO: Cell<c_int> = const { Cell::new(0) };
+    /// Transient error the next write made by this thread fails with.
+    static TRANSIENT: Cell<Option<c_int>> = const { Cell::new(None) };
+    static FAIL_CLOSE: Cell<bool> = const { Cell::new(false) };
+}
+
+fn state() -> MutexGuard<'static, State> {
+    // A test that panicked while holding the lock leaves the state usable.
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Wrapper allowing driver information, which holds raw pointers, in a static.
+struct Static<T>(T);
+
+unsafe impl<T> Sync for Static<T> {}
+
+macro_rules! cstr {
+    ($s:expr) => (concat!($s, "\0").as_ptr() as *const c_char)
+}
+
+static LIVE_OPTIONS: Static<[*const c_char; 4]> =
+    Static([cstr!("matrix"), cstr!("verbose"), cstr!("quiet"), cstr!("debug")]);
+static RAW_OPTIONS: Static<[*const c_char; 5]> =
+    Static([cstr!("byteorder"), cstr!("matrix"), cstr!("verbose"), cstr!("quiet"), cstr!("debug")]);
+
+static DRIVERS: Static<[ao_info; 4]> = Static([
+    ao_info {
+        flavor: ffi::AO_TYPE_LIVE,
+        name: cstr!("Null output"),
+        short_name: cstr!("null"),
+        author: cstr!("rust-ao"),
+        comment: cstr!("Discards all samples"),
+        preferred_byte_format: ffi::AO_FMT_NATIVE,
+        priority: 0,
+        options: &LIVE_OPTIONS.0 as *const _ as *const *const c_char,
+        option_count: 4,
+    },
+    ao_info {
+        flavor: ffi::AO_TYPE_FILE,
+        name: cstr!("WAV file output"),
+        short_name: cstr!("wav"),
+        author: cstr!("rust-ao"),
+        comment: cstr!("Writes samples to a WAV file"),
+        preferred_byte_format: ffi::AO_FMT_LITTLE,
+        priority: 0,
+        options: &LIVE_OPTIONS.0 as *const _ as *const *const c_char,
+        option_count: 4,
+    },
+    ao_info {
+        flavor: ffi::AO_TYPE_FILE,
+        name: cstr!("AU file output"),
+        short_name: cstr!("au"),
+        author: cstr!("rust-ao"),
+        comment: cstr!("Writes samples to a Sun AU file"),
+        preferred_byte_format: ffi::AO_FMT_BIG,
+        priority: 0,
+        options: &LIVE_OPTIONS.0 as *const _ as *const *const c_char,
+        option_count: 4,
+    },
+    ao_info {
+        flavor: ffi::AO_TYPE_FILE,
+        name: cstr!("RAW sample output"),
+        short_name: cstr!("raw"),
+        author: cstr!("rust-ao"),
+        comment: cstr!("Writes samples to a file, without a header"),
+        preferred_byte_format: ffi::AO_FMT_NATIVE,
+        priority: 0,
+        options: &RAW_OPTIONS.0 as *const _ as *const *const c_char,
+        option_count: 5,
+    },
+]);
+
+static DRIVER_LIST: Static<[*const ao_info; 4]> =
+    Static([&DRIVERS.0[0], &DRIVERS.0[1], &DRIVERS.0[2], &DRIVERS.0[3]]);
+
+fn driver(id: c_int) -> Option<&'static ao_info> {
+    if id < 0 {
+        None
+    } else {
+        DRIVERS.0.get(id as usize)
+    }
+}
+
+unsafe fn string(s: *const c_char) -> String {
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+fn fail<T>(code: c_int) -> *mut T {
+    ERRNO.with(|e| e.set(code));
+    ptr::null_mut()
+}
+
+pub(crate) fn errno() -> c_int {
+    ERRNO.with(|e| e.get())
+}
+
+pub(crate) fn clear_errno() {
+    ERRNO.with(|e| e.set(0));
+}
+
+pub(crate) unsafe fn ao_initialize() {}
+
+pub(crate) unsafe fn ao_shutdown() {}
+
+pub(crate) unsafe fn ao_driver_id(short_name: *const c_char) -> c_int {
+    let name = CStr::from_ptr(short_name);
+    DRIVERS.0.iter()
+              .position(|info| CStr::from_ptr(info.short_name) == name)
+              .map_or(-1, |id| id as c_int)
+}
+
+pub(crate) unsafe fn ao_default_driver_id() -> c_int {
+    0
+}
+
+pub(crate) unsafe fn ao_driver_info(driver_id: c_int) -> *const ao_info {
+    driver(driver_id).map_or(ptr::null(), |info| info as *const ao_info)
+}
+
+pub(crate) unsafe fn ao_driver_info_list(driver_count: *mut c_int) -> *const *const ao_info {
+    *driver_count = DRIVER_LIST.0.len() as c_int;
+    DRIVER_LIST.0.as_ptr()
+}
+
+pub(crate) unsafe fn ao_append_option(options: *mut *mut ao_option,
+                                      key: *const c_char,
+                                      value: *const c_char) -> c_int {
+    let node = Box::into_raw(Box::new(ao_option {
+        key: CStr::from_ptr(key).to_owned().into_raw(),
+        value: CStr::from_ptr(value).to_owned().into_raw(),
+        next: ptr::null_mut(),
+    }));
+    let mut tail = options;
+    while !(*tail).is_null() {
+        tail = &mut (**tail).next;
+    }
+    *tail = node;
+    1
+}
+
+pub(crate) unsafe fn ao_free_options(mut options: *mut ao_option) {
+    while !options.is_null() {
+        let node = Box::from_raw(options);
+        drop(CString::from_raw(node.key));
+        drop(CString::from_raw(node.value));
+        options = node.next;
+    }
+}
+
+pub(crate) unsafe fn ao_open_live(driver_id: c_int,
+                                  format: *const ao_sample_format,
+                                  options: *const ao_option) -> *mut ao_device {
+    open(driver_id, None, false, format, options)
+}
+
+pub(crate) unsafe fn ao_open_file(driver_id: c_int,
+                                  filename: *const c_char,
+                                  overwrite: c_int,
+                                  format: *const ao_sample_format,
+                                  options: *const ao_option) -> *mut ao_device {
+    let path = PathBuf::from(string(filename));
+    open(driver_id, Some(path), overwrite != 0, format, options)
+}
+
+unsafe fn open(driver_id: c_int, path: Option<PathBuf>, overwrite: bool,
+               format: *const ao_sample_format, options: *const ao_option) -> *mut ao_device {
+    let mut state = state();
+    if let Some(code) = state.fail_open.take() {
+        return fail(code);
+    }
+    let info = match driver(driver_id) {
+        Some(info) => info,
+        None => return fail(ffi::AO_ENODRIVER),
+    };
+    match (info.flavor, path.is_some()) {
+        (ffi::AO_TYPE_LIVE, true) => return fail(ffi::AO_ENOTFILE),
+        (ffi::AO_TYPE_FILE, false) => return fail(ffi::AO_ENOTLIVE),
+        _ => {}
+    }
+
+    let known = slice::from_raw_parts(info.options, info.option_count as usize);
+    let mut recorded = Vec::new();
+    let mut option = options;
+    while !option.is_null() {
+        let (key, value) = (string((*option).key), string((*option).value));
+        if !known.iter().any(|&k| CStr::from_ptr(k).to_bytes() == key.as_bytes()) {
+            return fail(ffi::AO_EBADOPTION);
+        }
+        if key == "byteorder" && !["native", "big", "little"].contains(&&value[..]) {
+            return fail(ffi::AO_EBADOPTION);
+        }
+        recorded.push((key, value));
+        option = (*option).next;
+    }
+
+    let format = &*format;
+    // Like libao, any width is accepted and stored in whole bytes.
+    if !(1..=32).contains(&format.bits) || format.rate <= 0 || format.channels <= 0 {
+        return fail(ffi::AO_EBADFORMAT);
+    }
+    let byte_order = match format.byte_format {
+        ffi::AO_FMT_LITTLE => Endianness::Little,
+        ffi::AO_FMT_BIG => Endianness::Big,
+        ffi::AO_FMT_NATIVE => Endianness::Native,
+        _ => return fail(ffi::AO_EBADFORMAT),
+    };
+
+    let mut output = match path {
+        Some(ref path) => {
+            let mut open = OpenOptions::new();
+            open.write(true);
+            if overwrite {
+                open.create(true).truncate(true);
+            } else {
+                open.create_new(true);
+            }
+            let (header, file_order) = match info.preferred_byte_format {
+                ffi::AO_FMT_LITTLE => (Header::Wav, Endianness::Little),
+                ffi::AO_FMT_BIG => (Header::Au, Endianness::Big),
+                _ => {
+                    let order = match recorded.iter().find(|o| o.0 == "byteorder") {
+                        Some(o) if o.1 == "big" => Endianness::Big,
+                        Some(o) if o.1 == "little" => Endianness::Little,
+                        _ => Endianness::Native,
+                    };
+                    (Header::None, order)
+                }
+            };
+            match open.open(path) {
+                Ok(file) => Some(FileOutput {
+                    file,
+                    header,
+                    swap: byte_order.is_big() != file_order.is_big(),
+                    width: (format.bits as usize).div_ceil(8),
+                    written: 0,
+                }),
+                Err(ref e) if e.kind() == ::std::io::ErrorKind::AlreadyExists => {
+                    return fail(ffi::AO_EFILEEXISTS);
+                }
+                Err(_) => return fail(ffi::AO_EOPENFILE),
+            }
+        }
+        None => None,
+    };
+
+    let record = MockDevice {
+        driver: string(info.short_name),
+        path,
+        bits: format.bits as usize,
+        sample_rate: format.rate as usize,
+        channels: format.channels as usize,
+        byte_order,
+        matrix: if format.matrix.is_null() {
+            None
+        } else {
+            Some(string(format.matrix))
+        },
+        options: recorded,
+        played: Vec::new(),
+        closed: false,
+    };
+    if let Some(ref mut output) = output {
+        if output.write_header(&record).is_err() {
+            return fail(ffi::AO_EOPENFILE);
+        }
+    }
+
+    let handle = state.next_handle;
+    state.next_handle += 1;
+    state.devices.insert(handle, OpenDevice { record, output });
+    handle as *mut ao_device
+}
+
+unsafe fn device(state: &mut State, handle: *mut ao_device) -> &mut OpenDevice {
+    state.devices.get_mut(&(handle as usize)).expect("Invalid device handle")
+}
+
+pub(crate) unsafe fn ao_close(handle: *mut ao_device) -> c_int {
+    let mut state = state();
+    let device = device(&mut state, handle);
+    let ok = match device.output.take() {
+        Some(mut output) => output.write_header(&device.record).is_ok(),
+        None => true,
+    };
+    device.record.closed = true;
+    if FAIL_CLOSE.with(|f| f.replace(false)) {
+        ERRNO.with(|e| e.set(libc::ENOSPC));
+        return 0;
+    }
+    ok as c_int
+}
+
+pub(crate) unsafe fn ao_play(handle: *mut ao_device,
+                             output_samples: *const c_char,
+                             num_bytes: u32) -> c_int {
+    let mut state = state();
+    if let Some(code) = TRANSIENT.with(|t| t.take()) {
+        ERRNO.with(|e| e.set(code));
+        return 0;
+    }
+    if let Some(code) = state.fail_play.take() {
+        ERRNO.with(|e| e.set(code));
+        return 0;
+    }
+    let bytes = slice::from_raw_parts(output_samples as *const u8, num_bytes as usize);
+    let device = device(&mut state, handle);
+    if let Some(ref mut output) = device.output {
+        if output.write(bytes).is_err() {
+            return 0;
+        }
+    }
+    device.record.played.extend_from_slice(bytes);
+    1
+}
+
+/// File drivers write exactly the bytes played, and refuse to replace files unless asked.
+#[test]
+fn test_mock_file_output() {
+    use super::{AO, SampleFormat};
+    use std::{env, fs, process};
+
+    let lib = AO::init();
+    let driver = lib.get_driver("raw").unwrap();
+    let format = SampleFormat::<i16, &'static str>::new(22050, 2, Endianness::Native, None);
+    let path = env::temp_dir().join(format!("ao-mock-{}.raw", process::id()));
+
+    driver.open_file(&format, &path, true).unwrap().play(&[1, 2]).unwrap();
+    assert_eq!(driver.open_file(&format, &path, false).err(), Some(AoError::FileExists));
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&1i16.to_ne_bytes());
+    expected.extend_from_slice(&2i16.to_ne_bytes());
+    assert_eq!(bytes, expected);
+    let device = devices().into_iter().find(|d| d.path.as_ref() == Some(&path)).unwrap();
+    assert_eq!(device.played, expected);
+}
@@ -0,0 +1,173 @@
+//! Output to several devices at once.
+//!
+//! A `MultiDevice` takes interleaved samples and fans them out to a number of devices, each of
+//! which receives a chosen subset of the input channels. For example, the front and rear pairs of
+//! a four-channel stream may be sent to two separate stereo sound cards.
+//!
+//! Independent devices run from independent clocks, so over time one will consume samples faster
+//! than another. libao provides no way to measure this, but if the difference is known (from
+//! hardware specifications, or by observing the devices drift apart) it can be compensated for
+//! with `MultiDevice::set_drift`, which periodically drops or repeats frames on one device.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::Native;
+//! use ao::multi::MultiDevice;
+//!
+//! fn main() {
+//!     let lib = AO::init();
+//!     let driver = lib.get_driver("").expect("No default driver available");
+//!     let stereo = SampleFormat::<i16, &'static str>::new(44100, 2, Native, None);
+//!
+//!     let mut multi = MultiDevice::new(4);
+//!     let front = multi.add(driver.open_live(&stereo).unwrap(), &[0, 1]).unwrap();
+//!     multi.add(driver.open_live(&stereo).unwrap(), &[2, 3]).unwrap();
+//!     // The first device's clock runs 20 parts per million fast.
+//!     multi.set_drift(front, 20);
+//!
+//!     multi.play(&[0; 4 * 4410]).expect("Playback failed");
+//! }
+//! ```
+
+use super::{AoError, AoResult, Device, Sample};
+
+/// One of the devices fed by a `MultiDevice`.
+struct Output<'a, S> {
+    device: Device<'a, S>,
+    /// Input channels sent to this device, in the order it receives them.
+    channels: Vec<usize>,
+    /// Clock difference to compensate for, in parts per million.
+    drift: i32,
+    /// Frames owed to (positive) or by (negative) this device, in millionths of a frame.
+    owed: i64,
+    /// Samples being prepared for the device, kept to avoid reallocating.
+    buffer: Vec<S>,
+}
+
+/// Plays interleaved samples through several devices.
+pub struct MultiDevice<'a, S> {
+    channels: usize,
+    outputs: Vec<Output<'a, S>>,
+}
+
+impl<'a, S: Sample> MultiDevice<'a, S> {
+    /// Create a `MultiDevice` accepting samples with `channels` interleaved channels.
+    ///
+    /// Samples are expected to be single-channel values such as `i16`, not frames such as
+    /// `[i16; 2]`.
+    pub fn new(channels: usize) -> MultiDevice<'a, S> {
+        MultiDevice {
+            channels,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Add a device, which will be sent the listed input channels.
+    ///
+    /// A channel may be sent to any number of devices. Returns an index identifying the device to
+    /// other methods.
+    ///
+    /// Returns `BadFormat` if the number of channels listed differs from the device's channel
+    /// count, since the device would otherwise be sent frames of the wrong size.
+    ///
+    /// Panics if any channel is not less than the number of input channels.
+    pub fn add(&mut self, device: Device<'a, S>, channels: &[usize]) -> AoResult<usize> {
+        for &c in channels {
+            assert!(c < self.channels, "MultiDevice has no input channel {}", c);
+        }
+        if channels.len() != device.channels {
+            return Err(AoError::BadFormat);
+        }
+        self.outputs.push(Output {
+            device,
+            channels: channels.to_vec(),
+            drift: 0,
+            owed: 0,
+            buffer: Vec::new(),
+        });
+        Ok(self.outputs.len() - 1)
+    }
+
+    /// Compensate for a device's clock running fast or slow.
+    ///
+    /// `ppm` is how much faster the device consumes samples than it should, in parts per million.
+    /// A positive value repeats a frame every `1_000_000 / ppm` frames to keep the device fed;
+    /// a negative value drops frames instead.
+    ///
+    /// Panics if `output` was not returned by `add`.
+    pub fn set_drift(&mut self, output: usize, ppm: i32) {
+        self.outputs[output].drift = ppm;
+    }
+
+    /// Get a device added to this `MultiDevice`.
+    pub fn device(&self, output: usize) -> Option<&Device<'a, S>> {
+        self.outputs.get(output).map(|o| &o.device)
+    }
+
+    /// Play interleaved samples through every device.
+    ///
+    /// Every device is written to even if some fail; the first error encountered is returned.
+    /// Trailing samples that do not make a complete frame are ignored.
+    pub fn play(&mut self, samples: &[S]) -> AoResult<()> {
+        let mut result = Ok(());
+        for output in &mut self.outputs {
+            output.owed = route(samples, self.channels, &output.channels, output.drift,
+                                output.owed, &mut output.buffer);
+            let played = output.device.play(&output.buffer);
+            if result.is_ok() {
+                result = played;
+            }
+        }
+        result
+    }
+}
+
+/// Extract `selected` channels from interleaved `input` into `out`, adjusting for drift.
+///
+/// `owed` carries the fractional frames owed to the device between calls; returns its new value.
+fn route<S: Copy>(input: &[S], channels: usize, selected: &[usize], drift: i32, owed: i64,
+                  out: &mut Vec<S>) -> i64 {
+    out.clear();
+    let mut owed = owed;
+    for frame in input.chunks_exact(channels.max(1)) {
+        owed += drift as i64;
+        if owed <= -1_000_000 {
+            // Device is slow: skip this frame entirely.
+            owed += 1_000_000;
+            continue;
+        }
+        let copies = if owed >= 1_000_000 {
+            owed -= 1_000_000;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            out.extend(selected.iter().map(|&c| frame[c]));
+        }
+    }
+    owed
+}
+
+/// Channels are picked out of each frame in the requested order.
+#[test]
+fn test_route_selects_channels() {
+    let mut out = Vec::new();
+    route(&[1, 2, 3, 4, 5, 6], 3, &[2, 0], 0, 0, &mut out);
+    assert_eq!(out, [3, 1, 6, 4]);
+}
+
+/// Drift compensation repeats or drops whole frames at the requested rate.
+#[test]
+fn test_route_drift() {
+    let input: Vec<i16> = (0..10).collect();
+    let mut out = Vec::new();
+
+    // 200000 ppm is one frame in five.
+    let owed = route(&input, 1, &[0], 200_000, 0, &mut out);
+    assert_eq!(out, [0, 1, 2, 3, 4, 4, 5, 6, 7, 8, 9, 9]);
+    assert_eq!(owed, 0);
+
+    route(&input, 1, &[0], -200_000, 0, &mut out);
+    assert_eq!(out, [0, 1, 2, 3, 5, 6, 7, 8]);
+}
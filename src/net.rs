@@ -0,0 +1,203 @@
+//! Streaming raw PCM over the network.
+//!
+//! A `NetworkSink` accepts the same interleaved samples as a `Device`, but sends them to a remote
+//! receiver instead of a local driver, so code producing audio can target either. Samples are sent
+//! as bare PCM over TCP or UDP, or over UDP as RTP with the L16 payload format (RFC 3551), which
+//! receivers such as GStreamer's `rtpL16depay` understand.
+//!
+//! ```no_run
+//! use ao::SampleFormat;
+//! use ao::Endianness::Native;
+//! use ao::net::NetworkSink;
+//!
+//! fn main() {
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 2, Native, None);
+//!     let mut sink = NetworkSink::rtp("0.0.0.0:0", "192.0.2.1:5004", &format)
+//!                                .expect("Failed to create socket");
+//!     sink.play(&[0; 4410]).expect("Failed to send");
+//! }
+//! ```
+
+use super::{simd, Sample, SampleFormat};
+use std::io::{self, Write};
+use std::mem::{size_of, size_of_val};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::slice;
+
+/// Largest UDP payload sent, small enough to avoid fragmentation on typical networks.
+const MAX_DATAGRAM: usize = 1400;
+
+/// Size of an RTP header without extensions or contributing sources.
+const RTP_HEADER: usize = 12;
+
+/// Dynamic payload type used for L16 formats without a static assignment.
+const RTP_DYNAMIC_PAYLOAD: u8 = 96;
+
+enum Transport {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+    Rtp(UdpSocket, RtpState),
+}
+
+struct RtpState {
+    payload_type: u8,
+    sequence: u16,
+    /// Timestamp of the next packet, in frames.
+    timestamp: u32,
+    ssrc: u32,
+}
+
+/// Sends samples to a remote receiver.
+pub struct NetworkSink<S> {
+    transport: Transport,
+    channels: usize,
+    /// Whether each sample's bytes must be reversed before sending.
+    swap: bool,
+    /// Bytes being prepared for sending, kept to avoid reallocating.
+    buffer: Vec<u8>,
+    marker: ::std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> NetworkSink<S> {
+    /// Stream bare PCM over a TCP connection to `addr`.
+    ///
+    /// Samples are sent as they are, in the byte order given by `format`, with no framing.
+    pub fn tcp<A: ToSocketAddrs, F>(addr: A,
+                                    format: &SampleFormat<S, F>) -> io::Result<NetworkSink<S>> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(NetworkSink::new(Transport::Tcp(stream), format))
+    }
+
+    /// Send bare PCM in UDP datagrams from `bind` to `target`.
+    ///
+    /// Each datagram holds whole frames, in the byte order given by `format`. UDP does not
+    /// guarantee delivery, so a receiver may see gaps.
+    pub fn udp<A: ToSocketAddrs, B: ToSocketAddrs, F>(bind: A, target: B,
+                                                      format: &SampleFormat<S, F>)
+            -> io::Result<NetworkSink<S>> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(target)?;
+        Ok(NetworkSink::new(Transport::Udp(socket), format))
+    }
+
+    /// Send RTP packets with the L16 payload format from `bind` to `target`.
+    ///
+    /// L16 carries 16-bit big-endian samples; they are converted from the byte order of
+    /// `format`. 44.1 kHz mono and stereo use their static payload types (11 and 10), and other
+    /// formats the dynamic type 96, which the receiver must be told of separately.
+    ///
+    /// Fails with `InvalidInput` if the samples are not 16 bits wide.
+    pub fn rtp<A: ToSocketAddrs, B: ToSocketAddrs, F>(bind: A, target: B,
+                                                      format: &SampleFormat<S, F>)
+            -> io::Result<NetworkSink<S>> {
+        if size_of::<S>() / S::silence().channels() != 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "RTP L16 requires 16-bit samples"));
+        }
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(target)?;
+
+        let payload_type = match (format.sample_rate, format.channels) {
+            (44100, 2) => 10,
+            (44100, 1) => 11,
+            _ => RTP_DYNAMIC_PAYLOAD,
+        };
+        let state = RtpState {
+            payload_type,
+            sequence: 0,
+            timestamp: 0,
+            ssrc: random_ssrc(&socket),
+        };
+        let mut sink = NetworkSink::new(Transport::Rtp(socket, state), format);
+        sink.swap = !format.byte_order.is_big();
+        Ok(sink)
+    }
+
+    fn new<F>(transport: Transport, format: &SampleFormat<S, F>) -> NetworkSink<S> {
+        NetworkSink {
+            transport,
+            channels: format.channels.max(1),
+            swap: false,
+            buffer: Vec::new(),
+            marker: ::std::marker::PhantomData,
+        }
+    }
+
+    /// Send samples to the receiver.
+    ///
+    /// Trailing samples that do not make a complete frame are still sent over TCP, but dropped
+    /// from datagrams so every datagram holds whole frames.
+    pub fn play(&mut self, samples: &[S]) -> io::Result<()> {
+        let bytes = unsafe {
+            slice::from_raw_parts(samples.as_ptr() as *const u8, size_of_val(samples))
+        };
+        let width = size_of::<S>() / S::silence().channels();
+        self.buffer.clear();
+        self.buffer.extend_from_slice(bytes);
+        if self.swap {
+            simd::swap_bytes(&mut self.buffer, width);
+        }
+
+        let frame_bytes = width * self.channels;
+        let datagram = (MAX_DATAGRAM / frame_bytes).max(1) * frame_bytes;
+        // Datagrams are cut at frame boundaries, so a trailing partial frame is left off first.
+        let whole = &self.buffer[..self.buffer.len() - self.buffer.len() % frame_bytes];
+        match self.transport {
+            Transport::Tcp(ref mut stream) => stream.write_all(&self.buffer),
+            Transport::Udp(ref socket) => {
+                for chunk in whole.chunks(datagram) {
+                    socket.send(chunk)?;
+                }
+                Ok(())
+            }
+            Transport::Rtp(ref socket, ref mut state) => {
+                let datagram = ((MAX_DATAGRAM - RTP_HEADER) / frame_bytes).max(1) * frame_bytes;
+                let mut packet = Vec::with_capacity(RTP_HEADER + datagram);
+                for chunk in whole.chunks(datagram) {
+                    packet.clear();
+                    state.header(&mut packet);
+                    packet.extend_from_slice(chunk);
+                    socket.send(&packet)?;
+                    state.sequence = state.sequence.wrapping_add(1);
+                    state.timestamp = state.timestamp.wrapping_add((chunk.len() / frame_bytes) as u32);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl RtpState {
+    /// Append the header for the next packet.
+    fn header(&self, out: &mut Vec<u8>) {
+        // Version 2, no padding, extension or contributing sources, no marker.
+        out.push(0x80);
+        out.push(self.payload_type & 0x7f);
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+    }
+}
+
+/// Pick a synchronization source identifier that is unlikely to collide with other senders.
+fn random_ssrc(socket: &UdpSocket) -> u32 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    RandomState::new().hash_one(socket.local_addr().ok()) as u32
+}
+
+/// RTP headers carry the version, payload type, sequence, timestamp and source in network order.
+#[test]
+fn test_rtp_header() {
+    let state = RtpState {
+        payload_type: 10,
+        sequence: 0x0102,
+        timestamp: 0x03040506,
+        ssrc: 0x0708090a,
+    };
+    let mut out = Vec::new();
+    state.header(&mut out);
+    assert_eq!(out, [0x80, 10, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+}
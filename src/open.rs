@@ -0,0 +1,150 @@
+//! Opening devices in a single expression.
+
+use super::{AO, AoError, AoResult, Device, Driver, DriverOptions, Sample, SampleFormat};
+use std::path::PathBuf;
+
+/// A driver named for `OpenOptions`.
+enum Choice {
+    Name(String),
+    Id(usize),
+}
+
+/// Gathers the driver, format, options and target needed to open a device.
+///
+/// Opening a device otherwise takes a driver from `AO`, a `SampleFormat`, and
+/// the right one of `Driver`'s open methods. This collects them in one place:
+///
+/// ```no_run
+/// use ao::{AO, OpenOptions, SampleFormat};
+/// use ao::Endianness::Native;
+///
+/// let lib = AO::init();
+/// let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+/// let device = OpenOptions::new(&lib, format)
+///     .driver("pulse")
+///     .driver("alsa")
+///     .option("client_name", "example")
+///     .open()
+///     .expect("No device could be opened");
+/// device.play(&[0; 88200]).unwrap();
+/// ```
+///
+/// Drivers are tried in the order given until one opens. With none given,
+/// live output uses `AO::find_live_default`, and file output the file driver
+/// named by the file's extension, such as `wav`.
+pub struct OpenOptions<'a, T, S> {
+    lib: &'a AO,
+    drivers: Vec<Choice>,
+    format: SampleFormat<T, S>,
+    options: DriverOptions,
+    /// The file to write and whether to overwrite it, or `None` for live output.
+    file: Option<(PathBuf, bool)>,
+}
+
+impl<'a, T: Sample, S: AsRef<str>> OpenOptions<'a, T, S> {
+    /// Prepare to open a live device playing `format`, with the default driver.
+    pub fn new(lib: &'a AO, format: SampleFormat<T, S>) -> OpenOptions<'a, T, S> {
+        OpenOptions {
+            lib,
+            drivers: Vec::new(),
+            format,
+            options: DriverOptions::new(),
+            file: None,
+        }
+    }
+
+    /// Try the driver called `name`, as for `AO::get_driver`, after any
+    /// already given.
+    pub fn driver<N: Into<String>>(mut self, name: N) -> OpenOptions<'a, T, S> {
+        self.drivers.push(Choice::Name(name.into()));
+        self
+    }
+
+    /// Try each of `names` in turn, after any drivers already given.
+    pub fn drivers<I>(mut self, names: I) -> OpenOptions<'a, T, S>
+            where I: IntoIterator, I::Item: Into<String> {
+        self.drivers.extend(names.into_iter().map(|n| Choice::Name(n.into())));
+        self
+    }
+
+    /// Try the driver with libao's numeric id `id`, after any already given.
+    ///
+    /// Ids number the drivers in the order `AO::drivers` lists them.
+    pub fn driver_id(mut self, id: usize) -> OpenOptions<'a, T, S> {
+        self.drivers.push(Choice::Id(id));
+        self
+    }
+
+    /// Set an option to pass to the driver, as `DriverOptions::set` does.
+    pub fn option<K: Into<String>, V: Into<String>>(mut self, key: K,
+                                                     value: V) -> OpenOptions<'a, T, S> {
+        self.options = self.options.set(key, value);
+        self
+    }
+
+    /// Replace every driver option set so far with `options`.
+    pub fn options(mut self, options: DriverOptions) -> OpenOptions<'a, T, S> {
+        self.options = options;
+        self
+    }
+
+    /// Open a live device. This is the default.
+    pub fn live(mut self) -> OpenOptions<'a, T, S> {
+        self.file = None;
+        self
+    }
+
+    /// Write to `path` with a file output driver, replacing any existing file
+    /// if `overwrite` is true.
+    pub fn file<P: Into<PathBuf>>(mut self, path: P, overwrite: bool) -> OpenOptions<'a, T, S> {
+        self.file = Some((path.into(), overwrite));
+        self
+    }
+
+    /// Open the device with the first driver that succeeds.
+    ///
+    /// Drivers that are not available are skipped. If no driver opens, the
+    /// error from the last one tried is returned, or `NoDriver` if none was
+    /// available.
+    pub fn open(&self) -> AoResult<Device<'a, T>> {
+        let mut error = AoError::NoDriver;
+        for driver in self.candidates() {
+            match self.open_with(&driver) {
+                Ok(device) => return Ok(device),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// The drivers to try, in order.
+    fn candidates(&self) -> Vec<Driver<'a>> {
+        let lib = self.lib;
+        if !self.drivers.is_empty() {
+            return self.drivers.iter().filter_map(|choice| match *choice {
+                Choice::Name(ref name) => lib.get_driver(name),
+                Choice::Id(id) => lib.drivers().nth(id),
+            }).collect();
+        }
+
+        match self.file {
+            Some((ref path, _)) => {
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                lib.file_drivers()
+                   .filter(|d| d.get_info().is_some_and(|i| i.short_name == extension))
+                   .take(1)
+                   .collect()
+            }
+            None => lib.find_live_default().into_iter().collect()
+        }
+    }
+
+    fn open_with(&self, driver: &Driver<'a>) -> AoResult<Device<'a, T>> {
+        match self.file {
+            Some((ref path, overwrite)) => {
+                driver.open_file_with(&self.format, path, overwrite, &self.options)
+            }
+            None => driver.open_live_with(&self.format, &self.options)
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Typed options for particular drivers.
+//!
+//! `DriverOptions` accepts any key and value, which is flexible but makes it easy to misspell an
+//! option or give it a value the driver will reject. The types in this module describe the
+//! options of individual drivers, and can check them against the format a device is to be opened
+//! with before libao sees them.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::{Big, Native};
+//! use ao::options::{DriverConfig, RawOptions};
+//! use std::path::Path;
+//!
+//! fn main() {
+//!     let lib = AO::init();
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 2, Native, None);
+//!     let raw = RawOptions::new(Big);
+//!     raw.validate(&format).expect("Options do not suit the format");
+//!
+//!     let driver = lib.get_driver(RawOptions::DRIVER).expect("No raw driver available");
+//!     let device = driver.open_file_with(&format, Path::new("out.raw"), true, &raw.to_options())
+//!                        .expect("Failed to open file");
+//!     device.play(&[0; 4410]).expect("Playback failed");
+//! }
+//! ```
+
+use super::{AoError, AoResult, DriverOptions, Endianness, Sample, SampleFormat};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Options for a specific driver.
+pub trait DriverConfig {
+    /// Short name of the driver the options apply to, as passed to `AO::get_driver`.
+    const DRIVER: &'static str;
+
+    /// Convert to the options passed to libao.
+    fn to_options(&self) -> DriverOptions;
+
+    /// Check that a device can be opened in `format` with these options.
+    ///
+    /// Returns the error libao would fail with, but with more specific detection of problems it
+    /// reports only as a generic failure. Does nothing by default.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        Ok(())
+    }
+}
+
+/// Check that a file header with fields of the given sizes can describe `format`.
+fn check_header<T, F>(format: &SampleFormat<T, F>, max_rate: u64,
+                      max_channels: u64) -> AoResult<()> {
+    if format.sample_rate as u64 > max_rate || format.channels as u64 > max_channels ||
+            format.channels == 0 {
+        Err(AoError::BadFormat)
+    } else {
+        Ok(())
+    }
+}
+
+/// Options for the `wav` file driver, which writes Microsoft WAVE files.
+///
+/// The driver has no options of its own, but validation catches formats a WAVE header cannot
+/// describe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WavOptions;
+
+impl DriverConfig for WavOptions {
+    const DRIVER: &'static str = "wav";
+
+    fn to_options(&self) -> DriverOptions {
+        DriverOptions::new()
+    }
+
+    /// WAVE headers store the channel count in 16 bits and the sample rate in 32.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        check_header(format, u32::MAX as u64, u16::MAX as u64)
+    }
+}
+
+/// Options for the `au` file driver, which writes Sun audio files.
+///
+/// The driver has no options of its own, but validation catches formats an au header cannot
+/// describe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuOptions;
+
+impl DriverConfig for AuOptions {
+    const DRIVER: &'static str = "au";
+
+    fn to_options(&self) -> DriverOptions {
+        DriverOptions::new()
+    }
+
+    /// au headers store the channel count and sample rate in 32 bits each.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        check_header(format, u32::MAX as u64, u32::MAX as u64)
+    }
+}
+
+/// Options for the `raw` file driver, which writes bare samples with no header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawOptions {
+    /// Byte order of the samples written to the file.
+    ///
+    /// Samples are converted from the byte order of the `SampleFormat` if it differs. The
+    /// driver's default is `Native`.
+    pub byte_order: Endianness,
+    /// Bits per sample written to the file.
+    ///
+    /// The driver writes samples at the width declared by the `SampleFormat`, and has no option
+    /// of its own for it, so this is not passed to libao. Instead `validate` fails with
+    /// `BadFormat` if the format declares a different width, so a file meant to hold samples of
+    /// this width is never written with any other. `None` accepts any width.
+    pub bits: Option<usize>,
+}
+
+impl RawOptions {
+    /// Write samples in the given byte order, of any width.
+    pub fn new(byte_order: Endianness) -> RawOptions {
+        RawOptions {
+            byte_order,
+            bits: None,
+        }
+    }
+}
+
+impl Default for RawOptions {
+    fn default() -> RawOptions {
+        RawOptions::new(Endianness::Native)
+    }
+}
+
+impl DriverConfig for RawOptions {
+    const DRIVER: &'static str = "raw";
+
+    fn to_options(&self) -> DriverOptions {
+        DriverOptions::new().set("byteorder", match self.byte_order {
+            Endianness::Little => "little",
+            Endianness::Big => "big",
+            Endianness::Native => "native",
+        })
+    }
+
+    /// Any format can be written raw, so long as it has at least one channel and the width
+    /// given by `bits`, if any.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        if self.bits.is_some_and(|bits| bits != format.bits()) {
+            return Err(AoError::BadFormat);
+        }
+        check_header(format, u64::MAX, u64::MAX)
+    }
+}
+
+/// Options for the `oss` live driver, the Open Sound System used on older Linux and some BSDs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OssOptions {
+    /// Path of the DSP device node to write to. The driver's default is `/dev/dsp`.
+    pub dsp: Option<PathBuf>,
+}
+
+impl OssOptions {
+    /// Write to the given DSP device node.
+    pub fn new<P: Into<PathBuf>>(dsp: P) -> OssOptions {
+        OssOptions {
+            dsp: Some(dsp.into()),
+        }
+    }
+
+    /// Get the device node that will be opened.
+    pub fn dsp_path(&self) -> &Path {
+        self.dsp.as_ref().map_or(Path::new("/dev/dsp"), |p| p.as_path())
+    }
+
+    /// Check that the device node exists and can be opened for writing.
+    ///
+    /// Opening a device fails with `OpenDevice`, carrying the underlying error as
+    /// `AoError::System` where it can be found. This checks beforehand, returning the underlying
+    /// error directly: it distinguishes a missing node (`ErrorKind::NotFound`), one the user
+    /// lacks permission for (`ErrorKind::PermissionDenied`) and one already in use by another
+    /// program (the OS error `EBUSY`). The node is opened without blocking and closed again
+    /// immediately.
+    pub fn check_device(&self) -> io::Result<()> {
+        super::check_device_node(self.dsp_path())
+    }
+}
+
+impl DriverConfig for OssOptions {
+    const DRIVER: &'static str = "oss";
+
+    /// A DSP path that is not valid UTF-8 cannot be passed to libao; opening a device with it
+    /// fails with `InvalidOption` rather than trying a mangled path.
+    fn to_options(&self) -> DriverOptions {
+        match self.dsp {
+            Some(ref path) => match path.to_str() {
+                Some(dsp) => DriverOptions::new().set("dsp", dsp),
+                None => DriverOptions::new().reject("dsp", path.to_string_lossy()),
+            },
+            None => DriverOptions::new(),
+        }
+    }
+
+    /// Fails with `InvalidOption` for `dsp`, as opening would, if the DSP path is not valid
+    /// UTF-8 and so cannot be passed to libao.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        match self.dsp {
+            Some(ref path) if path.to_str().is_none() => {
+                Err(AoError::InvalidOption("dsp".to_owned(), path.to_string_lossy().into_owned()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Add an option to `options` if it has a value.
+fn set_if<V: ToString>(options: DriverOptions, key: &str, value: &Option<V>) -> DriverOptions {
+    match *value {
+        Some(ref v) => options.set(key, v.to_string()),
+        None => options,
+    }
+}
+
+/// Check that an optional string can be passed to libao.
+fn check_string(value: &Option<String>) -> AoResult<()> {
+    match *value {
+        Some(ref v) if v.contains('\0') => Err(AoError::InvalidString(v.clone())),
+        _ => Ok(()),
+    }
+}
+
+/// Options for the `sndio` live driver, the native audio system of OpenBSD.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SndioOptions {
+    /// sndio device to play through, such as `snd/0` or `snd@host/0` for a remote server. The
+    /// driver's default is the user's default device, usually set by `AUDIODEVICE`.
+    pub dev: Option<String>,
+}
+
+impl DriverConfig for SndioOptions {
+    const DRIVER: &'static str = "sndio";
+
+    fn to_options(&self) -> DriverOptions {
+        set_if(DriverOptions::new(), "dev", &self.dev)
+    }
+
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        check_string(&self.dev)
+    }
+}
+
+/// Options for the `esd` live driver, which plays through the Enlightened Sound Daemon.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EsdOptions {
+    /// Host running the daemon, optionally followed by `:port`. The driver's default is the local
+    /// machine.
+    pub host: Option<String>,
+}
+
+impl EsdOptions {
+    /// Play through the daemon on a remote host, on the given port.
+    pub fn remote(host: &str, port: u16) -> EsdOptions {
+        EsdOptions {
+            host: Some(format!("{}:{}", host, port)),
+        }
+    }
+}
+
+impl DriverConfig for EsdOptions {
+    const DRIVER: &'static str = "esd";
+
+    fn to_options(&self) -> DriverOptions {
+        set_if(DriverOptions::new(), "host", &self.host)
+    }
+
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        check_string(&self.host)
+    }
+}
+
+/// Options for the `roar` live driver, which plays through a RoarAudio server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoarOptions {
+    /// Server to connect to: a host name, `host:port`, or the path of a UNIX socket. The driver's
+    /// default is found by the RoarAudio library, usually from `ROAR_SERVER`.
+    pub server: Option<String>,
+}
+
+impl DriverConfig for RoarOptions {
+    const DRIVER: &'static str = "roar";
+
+    fn to_options(&self) -> DriverOptions {
+        set_if(DriverOptions::new(), "server", &self.server)
+    }
+
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        check_string(&self.server)
+    }
+}
+
+/// Options for the `nas` live driver, which plays through a Network Audio System server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NasOptions {
+    /// Server to connect to, as `host:port`. The driver's default is taken from `AUDIOSERVER`
+    /// or `DISPLAY`.
+    pub host: Option<String>,
+    /// Size of the audio buffer on the server in bytes. The driver's default is 8192.
+    pub buf_size: Option<u32>,
+}
+
+impl DriverConfig for NasOptions {
+    const DRIVER: &'static str = "nas";
+
+    fn to_options(&self) -> DriverOptions {
+        set_if(set_if(DriverOptions::new(), "host", &self.host), "buf_size", &self.buf_size)
+    }
+
+    /// Fails with `BadOption` if the buffer size is zero.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        if self.buf_size == Some(0) {
+            return Err(AoError::BadOption);
+        }
+        check_string(&self.host)
+    }
+}
+
+/// Options for the `macosx` live driver, which plays through Core Audio.
+///
+/// Core Audio resamples and converts formats itself, so the driver accepts any sample rate and
+/// width; it has no options for latency beyond `buffer_time`, and no server or client naming.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MacOsxOptions {
+    /// Audio device to play through, by name or numeric ID. The driver's default is the
+    /// system's default output.
+    pub dev: Option<String>,
+    /// Size of the driver's buffer. The driver's default is 250 milliseconds.
+    pub buffer_time: Option<Duration>,
+}
+
+impl DriverConfig for MacOsxOptions {
+    const DRIVER: &'static str = "macosx";
+
+    fn to_options(&self) -> DriverOptions {
+        let options = set_if(DriverOptions::new(), "dev", &self.dev);
+        match self.buffer_time {
+            Some(t) => options.set("buffer_time", t.as_millis().to_string()),
+            None => options,
+        }
+    }
+
+    /// Fails with `BadOption` if the buffer time is under a millisecond.
+    fn validate<T: Sample, F: AsRef<str>>(&self, format: &SampleFormat<T, F>) -> AoResult<()> {
+        let _ = format;
+        if self.buffer_time.is_some_and(|t| t.as_millis() == 0) {
+            return Err(AoError::BadOption);
+        }
+        check_string(&self.dev)
+    }
+}
+
+/// Formats are checked against the limits of each file header.
+#[test]
+fn test_file_validation() {
+    use Endianness::Native;
+
+    let wide = SampleFormat::<i16, &'static str>::new(44100, 70000, Native, None);
+    assert_eq!(WavOptions.validate(&wide), Err(AoError::BadFormat));
+    assert_eq!(AuOptions.validate(&wide), Ok(()));
+    assert_eq!(RawOptions::default().to_options().get("byteorder"), Some("native"));
+
+    let narrow = SampleFormat::<i8, &'static str>::new(44100, 2, Native, None);
+    let raw = RawOptions { bits: Some(16), ..RawOptions::default() };
+    assert_eq!(raw.validate(&wide), Ok(()));
+    assert_eq!(raw.validate(&narrow), Err(AoError::BadFormat));
+}
+
+/// A missing DSP node is reported as such rather than as a generic failure.
+#[test]
+fn test_oss_missing_device() {
+    let oss = OssOptions::new("/nonexistent/dsp");
+    assert_eq!(oss.check_device().unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(oss.to_options().get("dsp"), Some("/nonexistent/dsp"));
+}
+
+/// A DSP path libao cannot be given is rejected, not passed on mangled.
+#[cfg(unix)]
+#[test]
+fn test_oss_non_utf8_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let oss = OssOptions::new(OsStr::from_bytes(b"/dev/dsp\xff"));
+    let format = SampleFormat::<i16, &'static str>::new(44100, 2, Endianness::Native, None);
+    let expected = AoError::InvalidOption("dsp".to_owned(), "/dev/dsp\u{fffd}".to_owned());
+    assert_eq!(oss.validate(&format), Err(expected));
+    assert_eq!(oss.to_options().get("dsp"), None);
+    assert!(!oss.to_options().is_empty());
+}
@@ -0,0 +1,1312 @@
+//! Background playback engine.
+//!
+//! `Device::play` blocks until the driver has accepted every sample it is given, and libao devices
+//! should only ever be driven from a single thread. This module owns a `Device` on a dedicated
+//! playback thread and exposes a `PlayerHandle` that queues audio and controls playback over a
+//! channel, so applications need not build that plumbing themselves.
+//!
+//! The playback thread may run for as long as the handle exists, so it requires a `Driver` that
+//! lives for the rest of the program.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::Native;
+//! use ao::player::PlayerHandle;
+//!
+//! fn main() {
+//!     let lib: &'static AO = Box::leak(Box::new(AO::init()));
+//!     let driver = lib.get_driver("").expect("No default driver available");
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 1, Native, None);
+//!
+//!     let player = PlayerHandle::spawn(driver, format).expect("Failed to open device");
+//!     player.set_volume(0.5);
+//!     player.enqueue(vec![0; 4410]);
+//! }
+//! ```
+
+use super::{AoError, AoResult, Device, Driver, DriverOptions, Sample, SampleFormat, TakeFrames};
+use super::{fire_cues, frames_in, take_cues, Cues};
+use std::collections::VecDeque;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// State of a playback engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The queue is empty and the engine is waiting for more samples.
+    Idle,
+    /// Queued samples are being written to the device.
+    Playing,
+    /// Playback has been paused with `PlayerHandle::pause`.
+    Paused,
+    /// Waiting for `PlayerOptions::prebuffer` worth of samples to be queued before starting.
+    Buffering,
+    /// The engine has shut down and closed its device.
+    Stopped,
+    /// The engine shut down because the device failed and could not be reopened.
+    Failed(AoError),
+}
+
+impl Status {
+    /// Whether the engine has shut down, either normally or due to failure.
+    pub fn is_stopped(&self) -> bool {
+        matches!(*self, Status::Stopped | Status::Failed(_))
+    }
+}
+
+/// Something noteworthy that happened on the playback thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Writing samples to the device failed.
+    PlayFailed(AoError),
+    /// Writing to the device blocked for this long beyond the duration of the samples written,
+    /// exceeding `PlayerOptions::stall_threshold`.
+    Stalled(Duration),
+    /// The device was reopened after a failure, on the given attempt (counting from 1).
+    Reopened(u32),
+    /// Attempting to reopen the device after a failure failed.
+    ReopenFailed(u32, AoError),
+}
+
+/// Configuration for a playback engine.
+#[derive(Debug, Clone)]
+pub struct PlayerOptions {
+    /// Maximum duration of audio that may be waiting in the queue.
+    ///
+    /// When the queue is full, `enqueue` blocks and `try_enqueue` fails until enough has been
+    /// played to make room. This stops fast producers such as decoders from racing ahead of
+    /// playback. `None` leaves the queue unbounded.
+    pub max_buffered: Option<Duration>,
+    /// Write silence to the device while paused.
+    ///
+    /// libao has no way to pause a device, so by default a paused engine simply stops writing
+    /// to it. Some drivers treat that as an underrun or close the stream, which silence avoids.
+    pub silence_while_paused: bool,
+    /// Write silence to the device while the queue is empty.
+    ///
+    /// If a stream starves, sound servers such as PulseAudio may suspend the output, causing a
+    /// delay and an audible pop when the next real samples arrive. Keeping the device fed
+    /// avoids this, at the cost of keeping the output awake.
+    pub silence_while_idle: bool,
+    /// How to retry opening the device when opening or writing to it fails.
+    ///
+    /// Devices commonly fail when a sound server restarts or hardware is unplugged, and reopening
+    /// the device often recovers. If every attempt after a failed write fails the engine stops
+    /// with `Status::Failed`.
+    pub retry: RetryPolicy,
+    /// How long a write may block beyond the duration of the samples written before
+    /// `Event::Stalled` is reported. The default is one second.
+    pub stall_threshold: Duration,
+    /// Duration of audio to wait for before writing anything to the device.
+    ///
+    /// Sources that are slow to start, such as network streams, tend to underrun immediately if
+    /// playback begins as soon as the first samples arrive. With this set, the engine waits
+    /// until enough samples have been queued with `enqueue`, or until a track is queued. Playback
+    /// also starts early if the queue fills up first under `max_buffered`, the queue is flushed,
+    /// `PlayerHandle::drain` is called, or every `PlayerHandle::sender` has been dropped. `None`
+    /// starts playback immediately.
+    pub prebuffer: Option<Duration>,
+    /// Number of frames between calls to `PlaybackObserver::progress`.
+    ///
+    /// `None` disables progress notifications.
+    pub progress_interval: Option<u64>,
+    /// Request realtime scheduling for the playback thread.
+    ///
+    /// A realtime thread is not preempted by ordinary processes, so playback is much less likely
+    /// to drop out when the system is under load. This usually requires privileges (such as
+    /// `CAP_SYS_NICE` or an `rtprio` limit on Linux); if the request is refused the thread runs
+    /// at normal priority, which `PlayerHandle::is_realtime` reports.
+    #[cfg(feature = "realtime")]
+    pub realtime: bool,
+    /// Estimated delay between writing samples to the device and hearing them.
+    ///
+    /// libao cannot report how much audio a device has buffered, so `PlayerHandle::drain` waits
+    /// this long after the last write before assuming playback has finished. The default is
+    /// 100 milliseconds.
+    pub latency: Duration,
+    /// Options passed to the driver whenever the device is opened.
+    pub driver_options: DriverOptions,
+}
+
+impl Default for PlayerOptions {
+    fn default() -> PlayerOptions {
+        PlayerOptions {
+            max_buffered: None,
+            silence_while_paused: false,
+            silence_while_idle: false,
+            retry: RetryPolicy::default(),
+            stall_threshold: Duration::from_secs(1),
+            prebuffer: None,
+            progress_interval: None,
+            #[cfg(feature = "realtime")]
+            realtime: false,
+            latency: Duration::from_millis(100),
+            driver_options: DriverOptions::new(),
+        }
+    }
+}
+
+/// Limits and delays for reopening a device that has failed.
+///
+/// The delay before each attempt grows geometrically from `initial_backoff` up to `max_backoff`,
+/// so a briefly unavailable device is recovered quickly while a missing one is not hammered.
+/// The engine handles no commands while waiting, so long delays also delay `stop`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of attempts before giving up. Zero disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first attempt.
+    pub initial_backoff: Duration,
+    /// Factor the delay is multiplied by after each attempt.
+    pub multiplier: f32,
+    /// Longest delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Get the delay before an attempt, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.saturating_sub(1) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * factor as f64;
+        Duration::from_secs_f64(delay.min(self.max_backoff.as_secs_f64()))
+    }
+}
+
+/// Three attempts, starting after 100 milliseconds and doubling up to two seconds.
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Identifies a track queued with `PlayerHandle::enqueue_track`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TrackId(u64);
+
+type Source<S> = Box<dyn Iterator<Item = Vec<S>> + Send>;
+
+type TrackEndCallback = Box<dyn FnMut(TrackId) + Send>;
+
+type EventCallback = Box<dyn FnMut(&Event) + Send>;
+
+/// Receives notifications about the progress of playback.
+///
+/// Register an observer with `PlayerHandle::add_observer`. Every method is called on the playback
+/// thread and does nothing by default, so implementations need only provide those they are
+/// interested in. Since they run on the playback thread, they should return quickly to avoid
+/// starving the device.
+pub trait PlaybackObserver: Send {
+    /// Playback has started, after the queue was empty or while prebuffering.
+    fn started(&mut self) {}
+    /// Playback has passed another `PlayerOptions::progress_interval` frames.
+    ///
+    /// `frames` is the total number of frames played from the queue, excluding silence.
+    fn progress(&mut self, frames: u64) {
+        let _ = frames;
+    }
+    /// The device has been reopened with a new sample rate or channel count.
+    fn format_changed(&mut self, sample_rate: usize, channels: usize) {
+        let _ = (sample_rate, channels);
+    }
+    /// The queue ran out of samples during playback.
+    fn underrun(&mut self) {}
+    /// The engine has stopped, normally or due to failure, and will play nothing more.
+    fn ended(&mut self) {}
+}
+
+/// Loudness normalization for a track, as from ReplayGain tags or an EBU R128 measurement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayGain {
+    /// Gain to apply, in decibels.
+    pub gain_db: f32,
+    /// Peak amplitude of the track as a fraction of full scale, if known.
+    ///
+    /// When present, less gain is applied if necessary to keep the peak from clipping.
+    pub peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Construct a ReplayGain specification.
+    pub fn new(gain_db: f32, peak: Option<f32>) -> ReplayGain {
+        ReplayGain {
+            gain_db,
+            peak,
+        }
+    }
+
+    /// Get the linear gain factor to apply, after clipping prevention.
+    pub fn factor(&self) -> f32 {
+        let gain = 10f32.powf(self.gain_db / 20.0);
+        match self.peak {
+            Some(peak) if peak > 0.0 => gain.min(1.0 / peak),
+            _ => gain
+        }
+    }
+}
+
+struct Track<S> {
+    id: TrackId,
+    format: SampleFormat<S, String>,
+    source: Source<S>,
+    /// Linear gain applied to this track, on top of the player volume.
+    gain: f32,
+    /// When the track was requested to start, if scheduled with `play_at`.
+    start: Option<Instant>,
+    /// Frames of silence remaining to be played before the track's samples.
+    lead: usize,
+}
+
+enum Item<S> {
+    /// Samples in the format the player was spawned with, and their length in frames.
+    Samples(Vec<S>, usize),
+    Track(Track<S>),
+}
+
+/// Where the engine sends its output.
+enum Target {
+    Live(DriverOptions),
+    /// A file, which is opened with `overwrite` the first time and never overwritten after, so
+    /// reopening it fails with `FileExists` rather than discard what was written.
+    File(PathBuf, bool, DriverOptions),
+}
+
+enum Command<S> {
+    Enqueue(Item<S>),
+    Switch(Driver<'static>, Target, Sender<AoResult<()>>),
+    OnTrackEnd(TrackEndCallback),
+    OnEvent(EventCallback),
+    AddObserver(Box<dyn PlaybackObserver>),
+    AtFrame(u64, Box<dyn FnOnce() + Send>),
+    SetVolume(f32),
+    /// End prebuffering, playing whatever is queued.
+    Start,
+    Flush,
+    Pause,
+    Resume,
+    Stop,
+}
+
+struct State {
+    status: Status,
+    /// Frames enqueued but not yet played.
+    queued: usize,
+    /// Items (samples or tracks) enqueued but not yet finished playing.
+    pending: usize,
+}
+
+/// State shared between a `PlayerHandle` and its playback thread.
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled whenever `state` changes.
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn set_status(&self, status: Status) {
+        self.lock().status = status;
+        self.changed.notify_all();
+    }
+
+    /// Account for queued items that have been played or discarded.
+    fn finished(&self, frames: usize, items: usize) {
+        let mut state = self.lock();
+        state.queued -= frames;
+        state.pending -= items;
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+/// Controls a playback thread.
+///
+/// Dropping the handle stops playback, discarding any samples still queued, and waits for the
+/// playback thread to close its device.
+pub struct PlayerHandle<S> {
+    feeder: Feeder<S>,
+    thread: Option<JoinHandle<()>>,
+    sample_rate: usize,
+    latency: Duration,
+    next_track: AtomicU64,
+    /// Whether the playback thread was given realtime scheduling.
+    realtime: bool,
+}
+
+impl<S: Sample + Send + 'static> PlayerHandle<S> {
+    /// Open a live device on a new playback thread.
+    ///
+    /// Returns once the device has been opened, or with the error encountered while opening it.
+    pub fn spawn<F>(driver: Driver<'static>, format: SampleFormat<S, F>) -> AoResult<PlayerHandle<S>>
+            where F: AsRef<str> + Send + 'static {
+        PlayerHandle::spawn_with(driver, format, PlayerOptions::default())
+    }
+
+    /// Open a live device on a new playback thread, with non-default options.
+    ///
+    /// If the device cannot be opened, opening is retried according to `options.retry` before
+    /// giving up.
+    pub fn spawn_with<F>(driver: Driver<'static>, format: SampleFormat<S, F>,
+                         options: PlayerOptions) -> AoResult<PlayerHandle<S>>
+            where F: AsRef<str> + Send + 'static {
+        let (commands, receiver) = mpsc::channel();
+        let (opened, open_result) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                status: Status::Idle,
+                queued: 0,
+                pending: 0,
+            }),
+            changed: Condvar::new(),
+        });
+        let sample_rate = format.sample_rate;
+        let channels = format.channels;
+        let max_queued = options.max_buffered.map(|d| {
+            (d.as_secs_f64() * sample_rate as f64) as usize
+        });
+        let latency = options.latency;
+
+        let thread_shared = shared.clone();
+        let format = owned_format(format);
+        let thread = thread::spawn(move || {
+            #[cfg(feature = "realtime")]
+            let realtime = options.realtime && raise_priority();
+            #[cfg(not(feature = "realtime"))]
+            let realtime = false;
+
+            let mut engine = match Engine::new(driver, format, receiver, &thread_shared, options) {
+                Ok(e) => {
+                    let _ = opened.send(Ok(realtime));
+                    e
+                }
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                    return;
+                }
+            };
+            let result = engine.run();
+            drop(engine);
+            thread_shared.set_status(match result {
+                Ok(()) => Status::Stopped,
+                Err(e) => Status::Failed(e),
+            });
+        });
+
+        let mut handle = PlayerHandle {
+            feeder: Feeder {
+                commands,
+                shared,
+                channels,
+                max_queued,
+                senders: Arc::new(AtomicUsize::new(0)),
+            },
+            thread: Some(thread),
+            sample_rate,
+            latency,
+            next_track: AtomicU64::new(0),
+            realtime: false,
+        };
+        match open_result.recv() {
+            Ok(Ok(realtime)) => {
+                handle.realtime = realtime;
+                Ok(handle)
+            }
+            Ok(Err(e)) => {
+                handle.join();
+                Err(e)
+            }
+            Err(_) => panic!("Playback thread exited while opening device"),
+        }
+    }
+
+    /// Open a live device on a new playback thread fed through a channel.
+    ///
+    /// Shorthand for `spawn` followed by `sender`. The handle must be kept to keep the engine
+    /// running.
+    pub fn spawn_channel<F>(driver: Driver<'static>, format: SampleFormat<S, F>)
+            -> AoResult<(PlayerHandle<S>, Sender<Vec<S>>)> where F: AsRef<str> + Send + 'static {
+        let handle = PlayerHandle::spawn(driver, format)?;
+        let sender = handle.sender();
+        Ok((handle, sender))
+    }
+
+    /// Append samples to the end of the playback queue.
+    ///
+    /// If the queue is bounded by `PlayerOptions::max_buffered`, blocks until there is room for
+    /// `samples`. A queue that is otherwise empty always accepts samples, however long.
+    ///
+    /// Samples enqueued after the engine has stopped are discarded.
+    pub fn enqueue(&self, samples: Vec<S>) {
+        self.feeder.enqueue(samples);
+    }
+
+    /// Append samples to the end of the playback queue without blocking.
+    ///
+    /// Returns the samples back if the queue does not have room for them.
+    pub fn try_enqueue(&self, samples: Vec<S>) -> Result<(), Vec<S>> {
+        self.feeder.try_enqueue(samples)
+    }
+
+    /// Get a channel that feeds samples to the end of the playback queue.
+    ///
+    /// Every buffer sent is passed to `enqueue` in order by a forwarding thread, so producers
+    /// written around `std::sync::mpsc` can drive the player directly. The channel itself is
+    /// unbounded: when `PlayerOptions::max_buffered` is reached, buffers wait in the channel
+    /// instead. The forwarding thread exits once every sender has been dropped or the engine has
+    /// stopped. Buffers still in the channel are not waited for by `drain` or discarded by
+    /// `flush`.
+    pub fn sender(&self) -> Sender<Vec<S>> {
+        let (sender, receiver) = mpsc::channel::<Vec<S>>();
+        let feeder = self.feeder.clone();
+        feeder.senders.fetch_add(1, Ordering::SeqCst);
+        thread::spawn(move || {
+            for samples in receiver {
+                if feeder.shared.lock().status.is_stopped() {
+                    break;
+                }
+                feeder.enqueue(samples);
+            }
+            // Only the last channel to close ends prebuffering.
+            if feeder.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+                feeder.send(Command::Start);
+            }
+        });
+        sender
+    }
+
+    /// Queue a track to play after everything already queued.
+    ///
+    /// The playback thread pulls chunks of samples from `source` as it plays them, until the
+    /// source is exhausted. If `format` differs from the format the device is open with, the
+    /// device is reopened when the track starts; consecutive tracks in the same format play back
+    /// to back with no gap between them.
+    ///
+    /// Tracks do not count toward `PlayerOptions::max_buffered`, since their samples are only
+    /// produced as they are played.
+    pub fn enqueue_track<I, F>(&self, source: I, format: SampleFormat<S, F>) -> TrackId
+            where I: IntoIterator<Item = Vec<S>>, I::IntoIter: Send + 'static, F: AsRef<str> {
+        self.queue_track(source, format, 1.0, None)
+    }
+
+    /// Queue a track with loudness normalization applied.
+    ///
+    /// As `enqueue_track`, but the track is played with `gain` applied in addition to the player
+    /// volume.
+    pub fn enqueue_track_with_gain<I, F>(&self, source: I, format: SampleFormat<S, F>,
+                                         gain: ReplayGain) -> TrackId
+            where I: IntoIterator<Item = Vec<S>>, I::IntoIter: Send + 'static, F: AsRef<str> {
+        self.queue_track(source, format, gain.factor(), None)
+    }
+
+    /// Queue a track to start playing at a particular time.
+    ///
+    /// As `enqueue_track`, but when the track reaches the front of the queue the engine plays
+    /// silence until `at`, so the first sample reaches the device on time. The device's own
+    /// buffering delays it further, so tracks scheduled on different players line up only to
+    /// within their devices' latency. If `at` has already passed when the track comes up, it
+    /// starts immediately; pausing delays it by the length of the pause.
+    pub fn play_at<I, F>(&self, at: Instant, source: I, format: SampleFormat<S, F>) -> TrackId
+            where I: IntoIterator<Item = Vec<S>>, I::IntoIter: Send + 'static, F: AsRef<str> {
+        self.queue_track(source, format, 1.0, Some(at))
+    }
+
+    /// Queue a track which stops after `duration`.
+    ///
+    /// As `enqueue_track`, but only `duration` of audio is taken from `source`; the buffer that
+    /// crosses it is cut short at a frame boundary.
+    pub fn play_for<I, F>(&self, source: I, format: SampleFormat<S, F>,
+                          duration: Duration) -> TrackId
+            where I: IntoIterator<Item = Vec<S>>, I::IntoIter: Send + 'static, F: AsRef<str> {
+        let frames = frames_in(duration, format.sample_rate);
+        let source = TakeFrames::new(source.into_iter(), frames, format.channels);
+        self.queue_track(source, format, 1.0, None)
+    }
+
+    fn queue_track<I, F>(&self, source: I, format: SampleFormat<S, F>, gain: f32,
+                         start: Option<Instant>) -> TrackId
+            where I: IntoIterator<Item = Vec<S>>, I::IntoIter: Send + 'static, F: AsRef<str> {
+        let id = TrackId(self.next_track.fetch_add(1, Ordering::Relaxed));
+        self.feeder.shared.lock().pending += 1;
+        self.send(Command::Enqueue(Item::Track(Track {
+            id,
+            format: owned_format(format),
+            source: Box::new(source.into_iter()),
+            gain,
+            start,
+            lead: 0,
+        })));
+        id
+    }
+
+    /// Set a function to be called whenever a track queued with `enqueue_track` finishes.
+    ///
+    /// The callback runs on the playback thread, and replaces any previously set. A track is
+    /// also considered finished if the device could not be opened in its format.
+    pub fn on_track_end<C>(&self, callback: C) where C: FnMut(TrackId) + Send + 'static {
+        self.send(Command::OnTrackEnd(Box::new(callback)));
+    }
+
+    /// Set a function to be called whenever the playback thread reports an `Event`.
+    ///
+    /// The callback runs on the playback thread, and replaces any previously set.
+    pub fn on_event<C>(&self, callback: C) where C: FnMut(&Event) + Send + 'static {
+        self.send(Command::OnEvent(Box::new(callback)));
+    }
+
+    /// Register an observer to be notified of playback progress.
+    pub fn add_observer<O: PlaybackObserver + 'static>(&self, observer: O) {
+        self.send(Command::AddObserver(Box::new(observer)));
+    }
+
+    /// Call `callback` once `frame` frames have been played from the queue.
+    ///
+    /// Frames are counted as for `PlaybackObserver::progress`, so silence played while paused or
+    /// idle does not count. The callback runs on the playback thread once the chunk crossing
+    /// `frame` has been written, ahead of when it is heard by the device's latency. A callback
+    /// for a frame already played runs after the next chunk.
+    pub fn at_frame<C>(&self, frame: u64, callback: C) where C: FnOnce() + Send + 'static {
+        self.send(Command::AtFrame(frame, Box::new(callback)));
+    }
+
+    /// Get the duration of audio waiting in the queue.
+    pub fn buffered(&self) -> Duration {
+        let frames = self.feeder.shared.lock().queued;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// Set the linear gain applied to samples as they are played.
+    ///
+    /// `1.0` plays samples unmodified. Negative values are treated as `0.0`.
+    pub fn set_volume(&self, volume: f32) {
+        self.send(Command::SetVolume(volume.max(0.0)));
+    }
+
+    /// Wait for everything queued so far to finish playing.
+    ///
+    /// Blocks until every sample and track in the queue has been written to the device, then for
+    /// a further `PlayerOptions::latency` to let the device play out what it has buffered. Returns
+    /// `false` if `timeout` elapsed first. Also returns once the engine has stopped, since
+    /// nothing more will be played; a paused engine is never drained.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        // Nothing more may be coming, so waiting for a prebuffer to fill could take forever.
+        self.send(Command::Start);
+        let mut state = self.feeder.shared.lock();
+        while state.pending > 0 && !state.status.is_stopped() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.feeder.shared.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        let stopped = state.status.is_stopped();
+        drop(state);
+
+        if !stopped {
+            let now = Instant::now();
+            if now + self.latency > deadline {
+                thread::sleep(deadline.saturating_duration_since(now));
+                return false;
+            }
+            thread::sleep(self.latency);
+        }
+        true
+    }
+
+    /// Discard everything in the queue.
+    ///
+    /// Samples and tracks queued before the call are dropped without being played, and
+    /// `on_track_end` is not called for discarded tracks. The chunk currently being written to
+    /// the device still completes.
+    pub fn flush(&self) {
+        self.send(Command::Flush);
+    }
+
+    /// Move playback to a live device opened with a different driver.
+    ///
+    /// The new device is opened in the current format and takes over from the next chunk of
+    /// samples, after which the old device is closed. Blocks until the switch has been made, or
+    /// fails leaving the old device in use. Returns `OpenDevice` if the engine has stopped.
+    ///
+    /// `options` replaces `PlayerOptions::driver_options` for subsequent opens.
+    pub fn switch_driver(&self, driver: Driver<'static>, options: DriverOptions) -> AoResult<()> {
+        self.switch(driver, Target::Live(options))
+    }
+
+    /// Move playback to a file opened with a file output driver.
+    ///
+    /// As `switch_driver`, with `path` and `overwrite` as for `Driver::open_file`. Switching to
+    /// the file already being written closes it first, so if the new device fails to open, none
+    /// is left in use.
+    ///
+    /// A file is not started over once written to. A track in a different format is skipped,
+    /// and after a failed write the file is closed and reopening it fails with `FileExists`.
+    pub fn switch_to_file(&self, driver: Driver<'static>, path: &Path, overwrite: bool,
+                          options: DriverOptions) -> AoResult<()> {
+        self.switch(driver, Target::File(path.to_owned(), overwrite, options))
+    }
+
+    fn switch(&self, driver: Driver<'static>, target: Target) -> AoResult<()> {
+        let (reply, result) = mpsc::channel();
+        self.send(Command::Switch(driver, target, reply));
+        result.recv().unwrap_or(Err(AoError::OpenDevice))
+    }
+
+    /// Pause playback.
+    ///
+    /// The queue is retained, and samples may still be enqueued while paused.
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    /// Resume playback after `pause`.
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// Stop playback and close the device.
+    ///
+    /// Any samples still in the queue are discarded. The engine cannot be restarted.
+    pub fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    /// Get the current state of the playback engine.
+    pub fn status(&self) -> Status {
+        self.feeder.shared.lock().status.clone()
+    }
+
+    /// Whether the playback thread is running with realtime scheduling.
+    ///
+    /// Always `false` unless `PlayerOptions::realtime` was requested and granted.
+    pub fn is_realtime(&self) -> bool {
+        self.realtime
+    }
+}
+
+impl<S> PlayerHandle<S> {
+    fn send(&self, command: Command<S>) {
+        self.feeder.send(command);
+    }
+
+    fn join(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<S> Drop for PlayerHandle<S> {
+    fn drop(&mut self) {
+        self.send(Command::Stop);
+        self.join();
+    }
+}
+
+/// Queues samples for a playback thread.
+///
+/// Shared by a `PlayerHandle` and the forwarding threads behind its `sender`s.
+struct Feeder<S> {
+    commands: Sender<Command<S>>,
+    shared: Arc<Shared>,
+    channels: usize,
+    max_queued: Option<usize>,
+    /// Forwarding threads still receiving from a `sender`.
+    senders: Arc<AtomicUsize>,
+}
+
+impl<S> Clone for Feeder<S> {
+    fn clone(&self) -> Feeder<S> {
+        Feeder {
+            commands: self.commands.clone(),
+            shared: self.shared.clone(),
+            channels: self.channels,
+            max_queued: self.max_queued,
+            senders: self.senders.clone(),
+        }
+    }
+}
+
+impl<S: Sample> Feeder<S> {
+    fn enqueue(&self, samples: Vec<S>) {
+        let frames = self.frames(&samples);
+        let mut state = self.shared.lock();
+        if self.is_full(&state, frames) {
+            // A queue too full to add to will never reach a longer prebuffer.
+            self.send(Command::Start);
+        }
+        while self.is_full(&state, frames) {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        state.queued += frames;
+        state.pending += 1;
+        drop(state);
+        self.send(Command::Enqueue(Item::Samples(samples, frames)));
+    }
+
+    fn try_enqueue(&self, samples: Vec<S>) -> Result<(), Vec<S>> {
+        let frames = self.frames(&samples);
+        let mut state = self.shared.lock();
+        if self.is_full(&state, frames) {
+            self.send(Command::Start);
+            return Err(samples);
+        }
+        state.queued += frames;
+        state.pending += 1;
+        drop(state);
+        self.send(Command::Enqueue(Item::Samples(samples, frames)));
+        Ok(())
+    }
+
+    fn frames(&self, samples: &[S]) -> usize {
+        match samples.first() {
+            Some(s) => samples.len() * s.channels() / self.channels.max(1),
+            None => 0
+        }
+    }
+
+    fn is_full(&self, state: &State, frames: usize) -> bool {
+        match self.max_queued {
+            Some(max) => {
+                !state.status.is_stopped() &&
+                    state.queued > 0 &&
+                    state.queued + frames > max
+            }
+            None => false
+        }
+    }
+}
+
+impl<S> Feeder<S> {
+    fn send(&self, command: Command<S>) {
+        // The only way to fail is if the playback thread has exited, in which case there's
+        // nothing left to act on the command.
+        let _ = self.commands.send(command);
+    }
+}
+
+/// Copy a sample format so it can be moved to the playback thread.
+fn owned_format<S, F: AsRef<str>>(format: SampleFormat<S, F>) -> SampleFormat<S, String> {
+    SampleFormat {
+        sample_rate: format.sample_rate,
+        channels: format.channels,
+        byte_order: format.byte_order,
+        matrix: format.matrix.map(|m| m.as_ref().to_owned()),
+        bits: format.bits,
+        marker: format.marker,
+    }
+}
+
+/// Switch the calling thread to realtime round-robin scheduling, returning whether it succeeded.
+///
+/// Uses a priority somewhat below the maximum, leaving room above for the system's own
+/// realtime threads such as interrupt handlers.
+#[cfg(all(feature = "realtime", unix))]
+fn raise_priority() -> bool {
+    unsafe {
+        let min = libc::sched_get_priority_min(libc::SCHED_RR);
+        let max = libc::sched_get_priority_max(libc::SCHED_RR);
+        if min < 0 || max < 0 {
+            return false;
+        }
+        let param = libc::sched_param {
+            sched_priority: (max - 10).max(min),
+        };
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_RR, &param) == 0
+    }
+}
+
+#[cfg(all(feature = "realtime", not(unix)))]
+fn raise_priority() -> bool {
+    false
+}
+
+/// Open a device for the engine's output.
+fn open<S: Sample>(driver: &Driver<'static>, target: &Target,
+                   format: &SampleFormat<S, String>) -> AoResult<Device<'static, S>> {
+    match *target {
+        Target::Live(ref options) => driver.open_live_with(format, options),
+        Target::File(ref path, overwrite, ref options) => {
+            driver.open_file_with(format, path, overwrite, options)
+        }
+    }
+}
+
+/// Open a device, retrying according to `retry` if it fails.
+///
+/// `emit` is told of the outcome of each retry, but not of the first attempt.
+fn open_retrying<S: Sample, E: FnMut(Event)>(driver: &Driver<'static>, target: &Target,
+                                             format: &SampleFormat<S, String>,
+                                             retry: &RetryPolicy,
+                                             mut emit: E) -> AoResult<Device<'static, S>> {
+    let mut attempt = 0;
+    loop {
+        match open(driver, target, format) {
+            Ok(d) => {
+                if attempt > 0 {
+                    emit(Event::Reopened(attempt));
+                }
+                return Ok(d);
+            }
+            Err(e) => {
+                if attempt > 0 {
+                    emit(Event::ReopenFailed(attempt, e.clone()));
+                }
+                if attempt >= retry.max_attempts {
+                    return Err(e);
+                }
+            }
+        }
+        attempt += 1;
+        thread::sleep(retry.backoff(attempt));
+    }
+}
+
+/// Build a short buffer of silence in a given format.
+///
+/// 10 milliseconds is short enough that commands are still handled promptly while writing it.
+fn silence<S: Sample>(format: &SampleFormat<S, String>) -> Vec<S> {
+    let frames = (format.sample_rate / 100).max(1);
+    let len = frames * format.channels / S::silence().channels();
+    vec![S::silence(); len.max(1)]
+}
+
+/// Playback thread state.
+struct Engine<'s, S> {
+    driver: Driver<'static>,
+    target: Target,
+    /// Format of samples queued with `enqueue`.
+    base_format: SampleFormat<S, String>,
+    /// Format the device is open with.
+    format: SampleFormat<S, String>,
+    /// The open device, or `None` if reopening it in a new format failed.
+    device: Option<Device<'static, S>>,
+    commands: Receiver<Command<S>>,
+    shared: &'s Shared,
+    options: PlayerOptions,
+    queue: VecDeque<Item<S>>,
+    /// A short buffer of silence, played while paused or idle if requested.
+    silence: Vec<S>,
+    on_track_end: Option<TrackEndCallback>,
+    on_event: Option<EventCallback>,
+    observers: Vec<Box<dyn PlaybackObserver>>,
+    /// Callbacks registered with `at_frame`, by frame.
+    cues: Cues<'static>,
+    /// Frames played from the queue.
+    frames_played: u64,
+    /// When `frames_played` next passes this, observers are notified of progress.
+    next_progress: u64,
+    status: Status,
+    volume: f32,
+    paused: bool,
+    /// Whether playback has yet to start, waiting for `prebuffer_frames` to be queued.
+    buffering: bool,
+    prebuffer_frames: usize,
+}
+
+impl<'s, S: Sample> Engine<'s, S> {
+    fn new(driver: Driver<'static>, format: SampleFormat<S, String>,
+           commands: Receiver<Command<S>>, shared: &'s Shared,
+           options: PlayerOptions) -> AoResult<Engine<'s, S>> {
+        let target = Target::Live(options.driver_options.clone());
+        let device = open_retrying(&driver, &target, &format, &options.retry, |_| ())?;
+        let prebuffer_frames = options.prebuffer.map_or(0, |d| {
+            (d.as_secs_f64() * format.sample_rate as f64) as usize
+        });
+
+        Ok(Engine {
+            driver,
+            target,
+            base_format: format.clone(),
+            silence: silence(&format),
+            format,
+            device: Some(device),
+            commands,
+            shared,
+            queue: VecDeque::new(),
+            on_track_end: None,
+            on_event: None,
+            observers: Vec::new(),
+            cues: Cues::new(),
+            frames_played: 0,
+            next_progress: options.progress_interval.unwrap_or(0),
+            status: Status::Idle,
+            volume: 1.0,
+            paused: false,
+            buffering: options.prebuffer.is_some(),
+            prebuffer_frames,
+            options,
+        })
+    }
+
+    /// Run until stopped, notifying observers when done.
+    fn run(&mut self) -> AoResult<()> {
+        let result = self.run_loop();
+        for o in self.observers.iter_mut() {
+            o.ended();
+        }
+        result
+    }
+
+    /// Playback thread main loop.
+    ///
+    /// Returns when told to stop or when the controlling handle goes away, or with an error if
+    /// the device failed and could not be recovered.
+    fn run_loop(&mut self) -> AoResult<()> {
+        loop {
+            self.update_status();
+
+            // Block for commands when there's nothing to play, otherwise only pick up what has
+            // already arrived so playback continues uninterrupted.
+            let command = if self.is_active() {
+                match self.commands.try_recv() {
+                    Ok(c) => Some(c),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match self.commands.recv() {
+                    Ok(c) => Some(c),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match command {
+                Some(Command::Enqueue(item)) => {
+                    if self.buffering {
+                        self.buffering = match item {
+                            Item::Samples(..) => self.shared.lock().queued < self.prebuffer_frames,
+                            Item::Track(_) => false,
+                        };
+                    }
+                    self.queue.push_back(item);
+                }
+                Some(Command::OnTrackEnd(callback)) => self.on_track_end = Some(callback),
+                Some(Command::OnEvent(callback)) => self.on_event = Some(callback),
+                Some(Command::AddObserver(observer)) => self.observers.push(observer),
+                Some(Command::AtFrame(frame, callback)) => {
+                    self.cues.entry(frame).or_default().push(callback);
+                }
+                Some(Command::SetVolume(v)) => self.volume = v,
+                Some(Command::Start) => self.buffering = false,
+                Some(Command::Flush) => {
+                    self.buffering = false;
+                    self.flush();
+                }
+                Some(Command::Switch(driver, target, reply)) => {
+                    let _ = reply.send(self.switch(driver, target));
+                }
+                Some(Command::Pause) => self.paused = true,
+                Some(Command::Resume) => self.paused = false,
+                Some(Command::Stop) => return Ok(()),
+                None if self.paused || self.queue.is_empty() => {
+                    let silence = mem::take(&mut self.silence);
+                    let result = self.write(&silence, 1.0);
+                    self.silence = silence;
+                    result?;
+                }
+                None => self.play_next()?,
+            }
+        }
+    }
+
+    /// Whether there is anything to write to the device.
+    fn is_active(&self) -> bool {
+        if self.paused {
+            self.options.silence_while_paused && self.device.is_some()
+        } else if self.buffering {
+            false
+        } else if self.queue.is_empty() {
+            self.options.silence_while_idle && self.device.is_some()
+        } else {
+            true
+        }
+    }
+
+    fn update_status(&mut self) {
+        let status = if self.paused {
+            Status::Paused
+        } else if self.buffering {
+            Status::Buffering
+        } else if self.queue.is_empty() {
+            Status::Idle
+        } else {
+            Status::Playing
+        };
+        if status == self.status {
+            return;
+        }
+
+        match (&self.status, &status) {
+            (&Status::Idle, &Status::Playing) | (&Status::Buffering, &Status::Playing) => {
+                for o in self.observers.iter_mut() {
+                    o.started();
+                }
+            }
+            (&Status::Playing, &Status::Idle) => {
+                for o in self.observers.iter_mut() {
+                    o.underrun();
+                }
+            }
+            _ => ()
+        }
+        self.status = status.clone();
+        self.shared.set_status(status);
+    }
+
+    fn emit(&mut self, event: Event) {
+        if let Some(ref mut callback) = self.on_event {
+            callback(&event);
+        }
+    }
+
+    fn play_next(&mut self) -> AoResult<()> {
+        match self.queue.pop_front() {
+            Some(Item::Samples(chunk, frames)) => {
+                let format = self.base_format.clone();
+                let result = self.play(&format, chunk, 1.0);
+                self.shared.finished(frames, 1);
+                result?;
+            }
+            Some(Item::Track(mut track)) => {
+                if let Some(at) = track.start.take() {
+                    let wait = at.saturating_duration_since(Instant::now());
+                    track.lead = (wait.as_secs_f64() * track.format.sample_rate as f64) as usize;
+                }
+                if track.lead > 0 {
+                    if self.lead_in(&mut track)? {
+                        self.queue.push_front(Item::Track(track));
+                    } else {
+                        self.track_ended(track.id);
+                    }
+                    return Ok(());
+                }
+
+                match track.source.next() {
+                    Some(chunk) => {
+                        if self.play(&track.format, chunk, track.gain)? {
+                            self.queue.push_front(Item::Track(track));
+                        } else {
+                            self.track_ended(track.id);
+                        }
+                    }
+                    None => self.track_ended(track.id),
+                }
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    /// Play part of the silence preceding a scheduled track.
+    ///
+    /// Silence is written in short pieces so commands are still handled while waiting. Returns
+    /// `false` if the device could not be opened in the track's format.
+    fn lead_in(&mut self, track: &mut Track<S>) -> AoResult<bool> {
+        if (self.device.is_none() || track.format != self.format) &&
+                self.reopen(&track.format).is_err() {
+            return Ok(false);
+        }
+
+        let frames = track.lead.min(self.silence.len() * S::silence().channels() /
+                                    track.format.channels.max(1));
+        let len = frames * track.format.channels / S::silence().channels();
+        let silence = mem::take(&mut self.silence);
+        let result = self.write(&silence[..len], 1.0);
+        self.silence = silence;
+        track.lead -= frames;
+        result.map(|()| true)
+    }
+
+    /// Play samples in the given format, reopening the device if required.
+    ///
+    /// `gain` is applied in addition to the player volume. Returns `false` if the device could
+    /// not be opened in that format.
+    fn play(&mut self, format: &SampleFormat<S, String>, chunk: Vec<S>,
+            gain: f32) -> AoResult<bool> {
+        if (self.device.is_none() || *format != self.format) && self.reopen(format).is_err() {
+            return Ok(false);
+        }
+
+        let gain = gain * self.volume;
+        self.write(&chunk, gain)?;
+
+        if let Some(s) = chunk.first() {
+            self.frames_played += (chunk.len() * s.channels() / format.channels.max(1)) as u64;
+            fire_cues(take_cues(&mut self.cues, self.frames_played));
+        }
+        if let Some(interval) = self.options.progress_interval {
+            if interval > 0 && self.frames_played >= self.next_progress {
+                let frames = self.frames_played;
+                for o in self.observers.iter_mut() {
+                    o.progress(frames);
+                }
+                while self.next_progress <= frames {
+                    self.next_progress += interval;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write samples to the device with `gain` applied, recovering from failures by reopening
+    /// it.
+    ///
+    /// The device applies the gain, so samples in either byte order are scaled correctly.
+    fn write(&mut self, samples: &[S], gain: f32) -> AoResult<()> {
+        let mut attempt = 0;
+        loop {
+            let start = Instant::now();
+            let result = match self.device {
+                Some(ref mut d) => {
+                    d.set_volume(gain);
+                    d.play(samples)
+                }
+                None => Err(AoError::OpenDevice),
+            };
+            let error = match result {
+                Ok(()) => {
+                    self.check_stall(samples, start.elapsed());
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            self.emit(Event::PlayFailed(error.clone()));
+
+            // Retry the same samples on a fresh device, until out of attempts.
+            loop {
+                if attempt == self.options.retry.max_attempts {
+                    return Err(error);
+                }
+                attempt += 1;
+                self.device = None;
+                thread::sleep(self.options.retry.backoff(attempt));
+                match open(&self.driver, &self.target, &self.format) {
+                    Ok(d) => {
+                        self.device = Some(d);
+                        self.emit(Event::Reopened(attempt));
+                        break;
+                    }
+                    Err(e) => self.emit(Event::ReopenFailed(attempt, e)),
+                }
+            }
+        }
+    }
+
+    /// Report a write that blocked for much longer than the audio it wrote.
+    fn check_stall(&mut self, samples: &[S], elapsed: Duration) {
+        let frames = match samples.first() {
+            Some(s) => samples.len() * s.channels() / self.format.channels.max(1),
+            None => return
+        };
+        let expected = Duration::from_secs_f64(frames as f64 / self.format.sample_rate.max(1) as f64);
+        if elapsed > expected + self.options.stall_threshold {
+            self.emit(Event::Stalled(elapsed - expected));
+        }
+    }
+
+    /// Switch the device to a new format.
+    ///
+    /// The new device is opened before the old one is closed so the gap between them is as short
+    /// as possible, unless the driver cannot have both open at once. A file is never overwritten,
+    /// so the old device is kept if the file cannot be opened again.
+    fn reopen(&mut self, format: &SampleFormat<S, String>) -> AoResult<()> {
+        let device = match open(&self.driver, &self.target, format) {
+            Ok(d) => d,
+            Err(e) if matches!(self.target, Target::File(..)) => return Err(e),
+            Err(_) => {
+                self.device = None;
+                let on_event = &mut self.on_event;
+                open_retrying(&self.driver, &self.target, format, &self.options.retry, |event| {
+                    if let Some(ref mut callback) = *on_event {
+                        callback(&event);
+                    }
+                })?
+            }
+        };
+        self.device = Some(device);
+        if format.sample_rate != self.format.sample_rate || format.channels != self.format.channels {
+            for o in self.observers.iter_mut() {
+                o.format_changed(format.sample_rate, format.channels);
+            }
+        }
+        self.format = format.clone();
+        self.silence = silence(format);
+        Ok(())
+    }
+
+    /// Replace the device with one opened from a different driver.
+    fn switch(&mut self, driver: Driver<'static>, target: Target) -> AoResult<()> {
+        if let (Target::File(ref old, ..), Target::File(ref new, ..)) = (&self.target, &target) {
+            // The old device finishes the file when closed, so it must be closed before the
+            // file is opened again.
+            if old == new {
+                self.device = None;
+            }
+        }
+        let device = open(&driver, &target, &self.format)?;
+        self.driver = driver;
+        self.target = match target {
+            // Reopening the file must not start it over.
+            Target::File(path, _, options) => Target::File(path, false, options),
+            target => target,
+        };
+        self.device = Some(device);
+        Ok(())
+    }
+
+    /// Discard everything in the queue.
+    fn flush(&mut self) {
+        let items = self.queue.len();
+        let frames = self.queue.drain(..).map(|item| match item {
+            Item::Samples(_, frames) => frames,
+            Item::Track(_) => 0,
+        }).sum();
+        self.shared.finished(frames, items);
+    }
+
+    fn track_ended(&mut self, id: TrackId) {
+        self.shared.finished(0, 1);
+        if let Some(ref mut callback) = self.on_track_end {
+            callback(id);
+        }
+    }
+}
+
+/// Backoff grows geometrically and is capped.
+#[test]
+fn test_retry_backoff() {
+    let retry = RetryPolicy::default();
+    assert_eq!(retry.backoff(1), Duration::from_millis(100));
+    assert_eq!(retry.backoff(3), Duration::from_millis(400));
+    assert_eq!(retry.backoff(10), Duration::from_secs(2));
+}
+
+/// Gain is converted from decibels and limited to keep the peak below full scale.
+#[test]
+fn test_replay_gain_factor() {
+    assert!((ReplayGain::new(-6.0, None).factor() - 0.501).abs() < 0.001);
+    assert!((ReplayGain::new(6.0, None).factor() - 1.995).abs() < 0.001);
+    assert_eq!(ReplayGain::new(6.0, Some(0.8)).factor(), 1.25);
+    assert!((ReplayGain::new(-6.0, Some(0.8)).factor() - 0.501).abs() < 0.001);
+}
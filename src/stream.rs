@@ -0,0 +1,225 @@
+//! Non-blocking streaming output.
+//!
+//! A `StreamWriter` hands samples to a lock-free ring buffer which is drained into a `Device` by a
+//! dedicated playback thread, so producers never block on `ao_play`. Each time the buffer runs dry
+//! while audio is playing the device starves; these underruns are counted so producers can tell
+//! when they are not keeping up.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::Native;
+//!
+//! fn main() {
+//!     let lib: &'static AO = Box::leak(Box::new(AO::init()));
+//!     let driver = lib.get_driver("").expect("No default driver available");
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 1, Native, None);
+//!     let device = driver.open_live(&format).expect("Failed to open device");
+//!
+//!     let mut writer = device.streaming_writer(8820);
+//!     let samples = vec![0i16; 4410];
+//!     let mut written = 0;
+//!     while written < samples.len() {
+//!         written += writer.write(&samples[written..]);
+//!     }
+//!     println!("{} underruns", writer.underruns());
+//! }
+//! ```
+
+use super::{Device, Sample, SendDevice};
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+
+/// Single-producer single-consumer ring of samples.
+///
+/// `head` and `tail` count samples read and written since creation; they index the buffer modulo
+/// its length. Only the writer advances `tail` and only the playback thread advances `head`.
+struct Ring<S> {
+    buffer: Box<[UnsafeCell<MaybeUninit<S>>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    closed: AtomicBool,
+    underruns: AtomicUsize,
+}
+
+// Slots are only ever accessed by one side at a time, as arbitrated by head and tail.
+unsafe impl<S: Send> Sync for Ring<S> {}
+
+impl<S: Copy> Ring<S> {
+    fn new(capacity: usize) -> Ring<S> {
+        Ring {
+            buffer: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            underruns: AtomicUsize::new(0),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    /// Copy as many samples as will fit into the ring. Writer side only.
+    fn push(&self, samples: &[S]) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        let free = self.capacity() - tail.wrapping_sub(head);
+        let count = free.min(samples.len());
+
+        for (i, &s) in samples[..count].iter().enumerate() {
+            let slot = &self.buffer[tail.wrapping_add(i) % self.capacity()];
+            unsafe {
+                (*slot.get()).write(s);
+            }
+        }
+        self.tail.store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Get the longest run of readable samples that is contiguous in memory. Reader side only.
+    ///
+    /// The samples remain valid until they are released with `consume`.
+    fn readable(&self) -> &[S] {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        let start = head % self.capacity();
+        let count = tail.wrapping_sub(head).min(self.capacity() - start);
+
+        unsafe {
+            let first = self.buffer.as_ptr().add(start) as *const S;
+            std::slice::from_raw_parts(first, count)
+        }
+    }
+
+    fn consume(&self, count: usize) {
+        let head = self.head.load(Ordering::Relaxed);
+        self.head.store(head.wrapping_add(count), Ordering::Release);
+    }
+}
+
+/// Feeds a device through a ring buffer without blocking.
+///
+/// Created with `Device::streaming_writer`. Dropping the writer waits for the samples already
+/// buffered to be played, then closes the device.
+pub struct StreamWriter<S> {
+    ring: Arc<Ring<S>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<S: Sample + Send + 'static> StreamWriter<S> {
+    pub(crate) fn new(device: Device<'static, S>, capacity: usize) -> StreamWriter<S> {
+        assert!(capacity > 0, "StreamWriter capacity must be non-zero");
+        let ring = Arc::new(Ring::new(capacity));
+        let device = SendDevice(device);
+
+        let thread_ring = ring.clone();
+        let thread = thread::spawn(move || {
+            let device = device;
+            drain(&device.0, &thread_ring);
+        });
+
+        StreamWriter {
+            ring,
+            thread: Some(thread),
+        }
+    }
+
+    /// Append as many samples as there is room for in the buffer.
+    ///
+    /// Never blocks. Returns the number of samples accepted, which may be zero if the buffer
+    /// is full.
+    pub fn write(&mut self, samples: &[S]) -> usize {
+        let count = self.ring.push(samples);
+        if count > 0 {
+            self.wake();
+        }
+        count
+    }
+
+    /// Append a single sample, returning `false` if the buffer is full.
+    pub fn push(&mut self, sample: S) -> bool {
+        self.write(&[sample]) == 1
+    }
+
+    /// Number of samples that can currently be written without being rejected.
+    pub fn free(&self) -> usize {
+        self.ring.capacity() - self.ring.len()
+    }
+
+    /// Number of times the buffer has run dry while audio was playing.
+    pub fn underruns(&self) -> usize {
+        self.ring.underruns.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> StreamWriter<S> {
+    fn wake(&self) {
+        if let Some(ref t) = self.thread {
+            t.thread().unpark();
+        }
+    }
+}
+
+impl<S> Drop for StreamWriter<S> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+        self.wake();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Playback thread main loop.
+fn drain<S: Sample>(device: &Device<S>, ring: &Ring<S>) {
+    let mut playing = false;
+
+    loop {
+        let samples = ring.readable();
+        if !samples.is_empty() {
+            playing = true;
+            // There's nobody to report a failed write to; those samples are lost just as they
+            // would be in an underrun.
+            let _ = device.play(samples);
+            let count = samples.len();
+            ring.consume(count);
+            continue;
+        }
+
+        // Check for closure before declaring an underrun so the final drain isn't counted.
+        if ring.closed.load(Ordering::Acquire) {
+            if ring.len() == 0 {
+                return;
+            }
+            continue;
+        }
+        if playing {
+            ring.underruns.fetch_add(1, Ordering::Relaxed);
+            playing = false;
+        }
+        thread::park();
+    }
+}
+
+/// Writes past the end of the buffer wrap around and are read back in order.
+#[test]
+fn test_ring_wraps() {
+    let ring = Ring::<i16>::new(4);
+    assert_eq!(ring.push(&[1, 2, 3]), 3);
+    assert_eq!(ring.readable(), &[1, 2, 3]);
+    ring.consume(2);
+
+    assert_eq!(ring.push(&[4, 5, 6, 7]), 3);
+    assert_eq!(ring.readable(), &[3, 4]);
+    ring.consume(2);
+    assert_eq!(ring.readable(), &[5, 6]);
+    ring.consume(2);
+    assert_eq!(ring.len(), 0);
+}
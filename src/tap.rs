@@ -0,0 +1,122 @@
+//! Access to the most recent audio played, for visualization.
+
+use std::marker::PhantomData;
+use std::mem::{size_of, size_of_val};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{fence, AtomicU64, AtomicU8, Ordering};
+
+/// The recent frames played through a device, readable from any thread.
+///
+/// Get a tap with `Device::tap`. The device copies every buffer it writes into a ring without
+/// locking, so the tap costs playback only that copy, and `snapshot` can be called from a UI
+/// thread to draw an oscilloscope or level meter. Clones of a tap read the same ring.
+pub struct SampleTap<S> {
+    ring: Arc<Ring>,
+    /// Bytes in each frame.
+    frame_bytes: usize,
+    /// Frames returned by `snapshot`.
+    frames: usize,
+    marker: PhantomData<S>,
+}
+
+/// A ring of bytes with one writer and any number of readers.
+///
+/// The writer announces the bytes it is about to overwrite in `claimed`, and publishes them in
+/// `written` once done; a reader that sees `claimed` reach the bytes it copied retries.
+struct Ring {
+    bytes: Box<[AtomicU8]>,
+    /// Total bytes the writer has started writing.
+    claimed: AtomicU64,
+    /// Total bytes the writer has finished writing.
+    written: AtomicU64,
+}
+
+impl<S> Clone for SampleTap<S> {
+    fn clone(&self) -> SampleTap<S> {
+        SampleTap {
+            ring: self.ring.clone(),
+            frame_bytes: self.frame_bytes,
+            frames: self.frames,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S> SampleTap<S> {
+    /// Create a tap keeping `frames` frames of `channels` channels.
+    pub(crate) fn new(frames: usize, channels: usize, per_value: usize) -> SampleTap<S> {
+        let frame_bytes = size_of::<S>() * channels.max(1) / per_value.max(1);
+        // Twice the frames snapshotted, so a reader can copy one half while the other is written.
+        let bytes = (0..frame_bytes * frames.max(1) * 2).map(|_| AtomicU8::new(0)).collect();
+        SampleTap {
+            ring: Arc::new(Ring {
+                bytes,
+                claimed: AtomicU64::new(0),
+                written: AtomicU64::new(0),
+            }),
+            frame_bytes,
+            frames,
+            marker: PhantomData,
+        }
+    }
+
+    /// Copy `samples` into the ring. Only the device that made the tap calls this.
+    pub(crate) fn push(&self, samples: &[S]) {
+        let ring = &*self.ring;
+        let len = ring.bytes.len();
+        let bytes = unsafe {
+            slice::from_raw_parts(samples.as_ptr() as *const u8, size_of_val(samples))
+        };
+        let start = ring.written.load(Ordering::Relaxed);
+        let end = start + bytes.len() as u64;
+        ring.claimed.store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // Bytes that would be overwritten within this buffer need not be written at all.
+        let skip = bytes.len().saturating_sub(len);
+        for (i, &b) in bytes[skip..].iter().enumerate() {
+            let at = (start + (skip + i) as u64) % len as u64;
+            ring.bytes[at as usize].store(b, Ordering::Relaxed);
+        }
+        ring.written.store(end, Ordering::Release);
+    }
+
+    /// Total frames played through the device since the tap was made.
+    pub fn frames_played(&self) -> u64 {
+        self.ring.written.load(Ordering::Acquire) / self.frame_bytes.max(1) as u64
+    }
+
+    /// Copy the most recent frames played, oldest first.
+    ///
+    /// Returns the number of frames the tap was made with, or fewer if not that many have been
+    /// played yet. If the device writes faster than the snapshot can be copied, the copy is
+    /// retried, so the samples returned are always frames that were played together.
+    pub fn snapshot(&self) -> Vec<S> {
+        let ring = &*self.ring;
+        let len = ring.bytes.len() as u64;
+        let mut bytes = Vec::with_capacity(self.frames * self.frame_bytes);
+        loop {
+            let written = ring.written.load(Ordering::Acquire);
+            let end = written - written % self.frame_bytes.max(1) as u64;
+            let start = end.saturating_sub((self.frames * self.frame_bytes) as u64);
+            bytes.clear();
+            bytes.extend((start..end).map(|i| ring.bytes[(i % len) as usize].load(Ordering::Relaxed)));
+
+            fence(Ordering::Acquire);
+            if ring.claimed.load(Ordering::Relaxed) <= start + len {
+                break;
+            }
+        }
+
+        let count = bytes.len() / size_of::<S>().max(1);
+        let mut samples = Vec::with_capacity(count);
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), samples.as_mut_ptr() as *mut u8,
+                                     count * size_of::<S>());
+            samples.set_len(count);
+        }
+        samples
+    }
+}
@@ -0,0 +1,123 @@
+//! Simultaneous output to a live device and a file.
+//!
+//! A `TeeDevice` writes every buffer to two devices, typically so audio can be monitored through
+//! speakers while it is captured to disk. The two outputs are independent: if writing to one of
+//! them fails it is closed, and playback continues on the other.
+//!
+//! ```
+//! use ao::{AO, SampleFormat};
+//! use ao::Endianness::Native;
+//! use ao::tee::TeeDevice;
+//! use std::path::Path;
+//!
+//! fn main() {
+//!     let lib = AO::init();
+//!     let live = lib.get_driver("").expect("No default driver available");
+//!     let file = lib.get_driver("wav").expect("No wav driver available");
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 1, Native, None);
+//!
+//!     let mut tee = TeeDevice::open(&live, &file, &format, Path::new("capture.wav"), true)
+//!                             .expect("Failed to open devices");
+//!     tee.play(&[0; 4410]).expect("Both outputs failed");
+//!     if let Some(e) = tee.file_error() {
+//!         println!("Capture stopped: {}", e);
+//!     }
+//! }
+//! ```
+
+use super::{AoError, AoResult, Device, Driver, Sample, SampleFormat};
+use std::path::Path;
+
+/// One of the two outputs of a `TeeDevice`.
+enum Leg<'a, S> {
+    Open(Device<'a, S>),
+    Failed(AoError),
+}
+
+impl<'a, S: Sample> Leg<'a, S> {
+    fn play(&mut self, samples: &[S]) {
+        let result = match *self {
+            Leg::Open(ref d) => d.play(samples),
+            Leg::Failed(_) => return,
+        };
+        if let Err(e) = result {
+            // Replacing the device closes it.
+            *self = Leg::Failed(e);
+        }
+    }
+
+    fn device(&self) -> Option<&Device<'a, S>> {
+        match *self {
+            Leg::Open(ref d) => Some(d),
+            Leg::Failed(_) => None,
+        }
+    }
+
+    fn error(&self) -> Option<&AoError> {
+        match *self {
+            Leg::Open(_) => None,
+            Leg::Failed(ref e) => Some(e),
+        }
+    }
+}
+
+/// Plays the same samples through a live device and a file device.
+pub struct TeeDevice<'a, S> {
+    live: Leg<'a, S>,
+    file: Leg<'a, S>,
+}
+
+impl<'a, S: Sample> TeeDevice<'a, S> {
+    /// Combine an already-open live device and file device.
+    pub fn new(live: Device<'a, S>, file: Device<'a, S>) -> TeeDevice<'a, S> {
+        TeeDevice {
+            live: Leg::Open(live),
+            file: Leg::Open(file),
+        }
+    }
+
+    /// Open both devices with the same format.
+    ///
+    /// `path` and `overwrite` are as for `Driver::open_file`. Fails if either device cannot be
+    /// opened.
+    pub fn open<F: AsRef<str>>(live: &Driver<'a>, file: &Driver<'a>, format: &SampleFormat<S, F>,
+                               path: &Path, overwrite: bool) -> AoResult<TeeDevice<'a, S>> {
+        let live = live.open_live(format)?;
+        let file = file.open_file(format, path, overwrite)?;
+        Ok(TeeDevice::new(live, file))
+    }
+
+    /// Play samples through both devices.
+    ///
+    /// A device that fails is closed and receives no further samples; its error is available
+    /// from `live_error` or `file_error`. Returns an error only once both have failed.
+    pub fn play(&mut self, samples: &[S]) -> AoResult<()> {
+        self.live.play(samples);
+        self.file.play(samples);
+
+        match (self.live.error(), self.file.error()) {
+            (Some(e), Some(_)) => Err(e.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the live device, unless it has failed.
+    pub fn live(&self) -> Option<&Device<'a, S>> {
+        self.live.device()
+    }
+
+    /// Get the file device, unless it has failed.
+    pub fn file(&self) -> Option<&Device<'a, S>> {
+        self.file.device()
+    }
+
+    /// Get the error that caused the live device to be closed, if any.
+    pub fn live_error(&self) -> Option<&AoError> {
+        self.live.error()
+    }
+
+    /// Get the error that caused the file device to be closed, if any.
+    pub fn file_error(&self) -> Option<&AoError> {
+        self.file.error()
+    }
+}
@@ -0,0 +1,146 @@
+//! Helpers for testing code that plays audio.
+//!
+//! A `CaptureDevice` accepts samples the way a `Device` does, but keeps the bytes it would have
+//! handed to libao in memory instead of playing them. It needs neither libao nor an output, so
+//! tests can assert exactly what was produced for some input.
+//!
+//! ```
+//! use ao::SampleFormat;
+//! use ao::Endianness::Big;
+//! use ao::testing::CaptureDevice;
+//!
+//! fn main() {
+//!     let format = SampleFormat::<i16, &'static str>::new(44100, 1, Big, None);
+//!     let mut device = CaptureDevice::new(&format);
+//!     device.set_volume(0.5);
+//!     device.play(&[1000i16.to_be(), (-2i16).to_be()]).unwrap();
+//!
+//!     assert_eq!(&*device.bytes(), &[0x01, 0xf4, 0xff, 0xff]);
+//!     assert_eq!(device.position().frames, 2);
+//! }
+//! ```
+
+use super::{swap_samples, AoResult, Position, Sample, SampleFormat};
+use std::cell::{Cell, Ref, RefCell};
+use std::marker::PhantomData;
+use std::mem::size_of_val;
+use std::slice;
+use std::time::Instant;
+
+/// A device that records what is played to it.
+pub struct CaptureDevice<S> {
+    sample_rate: usize,
+    channels: usize,
+    /// Whether samples are in the opposite byte order to this machine's, and must be swapped
+    /// around any arithmetic on them.
+    swap: bool,
+    bytes: RefCell<Vec<u8>>,
+    frames: Cell<u64>,
+    written_at: Cell<Instant>,
+    volume: f32,
+    muted: bool,
+    marker: PhantomData<S>,
+}
+
+impl<S: Sample> CaptureDevice<S> {
+    /// Create a capture which records samples in `format`.
+    ///
+    /// As with a `Device`, samples are expected to be given in the byte order of `format`, and
+    /// are recorded unchanged apart from volume. The channel matrix is not applied.
+    pub fn new<F>(format: &SampleFormat<S, F>) -> CaptureDevice<S> {
+        CaptureDevice {
+            sample_rate: format.sample_rate,
+            channels: format.channels,
+            swap: format.byte_order.is_foreign(),
+            bytes: RefCell::new(Vec::new()),
+            frames: Cell::new(0),
+            written_at: Cell::new(Instant::now()),
+            volume: 1.0,
+            muted: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Set the linear gain applied to samples in `play`, as `Device::set_volume` does.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.max(0.0);
+    }
+
+    /// Get the linear gain applied to samples in `play`.
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Record silence in place of any samples, until `unmute` is called.
+    pub fn mute(&mut self) {
+        self.muted = true;
+    }
+
+    /// Resume recording samples after `mute`.
+    pub fn unmute(&mut self) {
+        self.muted = false;
+    }
+
+    /// Whether the device is muted.
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Get the amount of audio played so far.
+    pub fn position(&self) -> Position {
+        Position::new(self.frames.get(), self.sample_rate, self.written_at.get())
+    }
+
+    /// Record packed samples, with volume applied as `Device::play` would.
+    ///
+    /// Never fails; the result matches `Device::play` so either may be used.
+    pub fn play(&self, samples: &[S]) -> AoResult<()> {
+        let scaled: Vec<S>;
+        let samples = if self.muted {
+            scaled = vec![S::silence(); samples.len()];
+            &scaled[..]
+        } else if self.volume != 1.0 {
+            let mut values = samples.to_vec();
+            if self.swap {
+                swap_samples(&mut values);
+            }
+            for s in values.iter_mut() {
+                *s = s.scale(self.volume);
+            }
+            if self.swap {
+                swap_samples(&mut values);
+            }
+            scaled = values;
+            &scaled[..]
+        } else {
+            samples
+        };
+
+        let raw = unsafe {
+            slice::from_raw_parts(samples.as_ptr() as *const u8, size_of_val(samples))
+        };
+        self.bytes.borrow_mut().extend_from_slice(raw);
+
+        if let Some(s) = samples.first() {
+            let frames = samples.len() * s.channels() / self.channels.max(1);
+            self.frames.set(self.frames.get() + frames as u64);
+            self.written_at.set(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Get everything played so far.
+    pub fn bytes(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.bytes.borrow(), |b| &b[..])
+    }
+
+    /// Discard everything played so far, keeping the position.
+    pub fn clear(&self) {
+        self.bytes.borrow_mut().clear();
+    }
+
+    /// Consume the device, returning everything played to it.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes.into_inner()
+    }
+}
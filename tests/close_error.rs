@@ -0,0 +1,43 @@
+//! Failures to close a device are returned by `close`, and passed to the handler when dropped.
+//!
+//! The handler is shared by the whole process, so this runs in a process of its own.
+
+extern crate ao;
+
+use ao::{AO, SampleFormat};
+use ao::Endianness::Native;
+use std::sync::mpsc;
+
+#[test]
+fn test_close_errors() {
+    let lib = AO::init();
+    let driver = match lib.get_driver("null") {
+        Some(d) => d,
+        None => return,
+    };
+    let (sender, reported) = mpsc::channel();
+    ao::set_close_error_handler(move |e| sender.send(e.clone()).unwrap());
+    let format = SampleFormat::<i16, &str>::new(44100, 1, Native, None);
+
+    let device = driver.open_live(&format).expect("Failed to open null device");
+    device.close().expect("Closing failed");
+    drop(driver.open_live(&format).expect("Failed to open null device"));
+    assert!(reported.try_recv().is_err(), "Handler called for a clean close");
+
+    #[cfg(feature = "mock")]
+    {
+        use ao::AoError;
+        use std::io::ErrorKind;
+
+        ao::mock::fail_next_close();
+        let device = driver.open_live(&format).expect("Failed to open null device");
+        let error = device.close().unwrap_err();
+        assert_eq!(*error.kind(), AoError::Close);
+        assert_eq!(error.os_error().map(|e| e.kind()), Some(ErrorKind::StorageFull));
+        assert!(reported.try_recv().is_err(), "Handler called for an explicit close");
+
+        ao::mock::fail_next_close();
+        drop(driver.open_live(&format).expect("Failed to open null device"));
+        assert_eq!(reported.try_recv().map(|e| e.kind().clone()), Ok(AoError::Close));
+    }
+}
@@ -0,0 +1,43 @@
+//! Default options set on the `AO` are merged into every device opened.
+//!
+//! Defaults affect the whole process, so this runs in a process of its own.
+
+extern crate ao;
+
+use ao::{AO, DriverOptions, SampleFormat};
+use ao::Endianness::Native;
+use std::env;
+use std::fs;
+use std::process;
+
+#[test]
+fn test_default_options() {
+    let lib = AO::init();
+    let (null, raw) = match (lib.get_driver("null"), lib.get_driver("raw")) {
+        (Some(null), Some(raw)) => (null, raw),
+        _ => {
+            eprintln!("libao has no null or raw driver; skipping");
+            return;
+        }
+    };
+    let format = SampleFormat::<i16, &str>::new(8000, 1, Native, None);
+
+    // Defaults the driver does not accept are skipped rather than rejected.
+    lib.set_default_option("byteorder", "big");
+    lib.set_default_option("no-such-option", "1");
+    null.open_live(&format).expect("Default option rejected");
+
+    let path = env::temp_dir().join(format!("ao-defaults-{}.raw", process::id()));
+    raw.open_file(&format, &path, true).expect("Failed to open raw file").play(&[1]).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), 1i16.to_be_bytes());
+
+    // Options given when opening take precedence.
+    let little = DriverOptions::new().set("byteorder", "little");
+    raw.open_file_with(&format, &path, true, &little).unwrap().play(&[1]).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), 1i16.to_le_bytes());
+
+    lib.set_default_options(DriverOptions::new());
+    raw.open_file(&format, &path, true).unwrap().play(&[1]).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), 1i16.to_ne_bytes());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,36 @@
+//! Once a `MainThreadToken` is acquired, `AO` may only be created on its thread.
+//!
+//! Acquiring a token affects the whole process, so this runs in a process of its own, and without
+//! the test harness, which would run it on a thread other than the main thread.
+
+extern crate ao;
+
+use ao::{AO, MainThreadToken};
+use std::panic;
+use std::thread;
+
+fn main() {
+    // The panics expected of other threads are not worth reporting.
+    let report = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            report(info);
+        }
+    }));
+
+    // On platforms where it can be checked, no thread but the main thread may acquire a token,
+    // even the first to try.
+    let spawned = thread::spawn(|| { MainThreadToken::acquire(); }).join();
+    if cfg!(any(target_os = "linux", target_vendor = "apple")) {
+        assert!(spawned.is_err(), "Token acquired off the main thread");
+    }
+
+    let token = MainThreadToken::acquire();
+    assert!(thread::spawn(AO::try_init).join().is_err(), "AO created off the main thread");
+    assert!(thread::spawn(|| { MainThreadToken::acquire(); }).join().is_err());
+
+    let lib = AO::init_on(&token);
+    drop(lib);
+    drop(AO::init());
+    println!("test_main_thread_token ... ok");
+}
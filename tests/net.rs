@@ -0,0 +1,97 @@
+//! Send samples through `NetworkSink` to receivers on the loopback interface.
+
+extern crate ao;
+
+use ao::Endianness::{Big, Little, Native};
+use ao::SampleFormat;
+use ao::net::NetworkSink;
+use std::io::Read;
+use std::net::{TcpListener, UdpSocket};
+use std::time::Duration;
+
+fn receiver() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("Failed to bind receiver");
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket
+}
+
+/// Receive `count` datagrams.
+fn receive(socket: &UdpSocket, count: usize) -> Vec<Vec<u8>> {
+    let mut buf = [0; 2048];
+    (0..count).map(|_| {
+        let len = socket.recv(&mut buf).expect("Datagram not received");
+        buf[..len].to_vec()
+    }).collect()
+}
+
+fn bytes(samples: &[i16], big: bool) -> Vec<u8> {
+    samples.iter().flat_map(|&s| if big { s.to_be_bytes() } else { s.to_ne_bytes() }).collect()
+}
+
+/// TCP carries every sample unchanged, including a trailing partial frame.
+#[test]
+fn test_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+    let samples: Vec<i16> = (0..1001).collect();
+
+    let mut sink = NetworkSink::tcp(listener.local_addr().unwrap(), &format).expect("Failed to connect");
+    let (mut stream, _) = listener.accept().unwrap();
+    sink.play(&samples).expect("Failed to send");
+    drop(sink);
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).unwrap();
+    assert_eq!(received, bytes(&samples, false));
+}
+
+/// UDP splits samples into datagrams of whole frames, leaving off only a trailing partial frame.
+#[test]
+fn test_udp() {
+    let socket = receiver();
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+    // 500 frames is 2000 bytes: one full 1400-byte datagram, one of 600, and a partial frame.
+    let samples: Vec<i16> = (0..1001).collect();
+
+    let mut sink = NetworkSink::udp("127.0.0.1:0", socket.local_addr().unwrap(), &format)
+        .expect("Failed to create socket");
+    sink.play(&samples).expect("Failed to send");
+
+    let datagrams = receive(&socket, 2);
+    assert_eq!(datagrams.iter().map(|d| d.len()).collect::<Vec<_>>(), [1400, 600]);
+    assert_eq!(datagrams.concat(), bytes(&samples[..1000], false));
+}
+
+/// RTP packets carry big-endian samples whatever the byte order given, and count frames.
+#[test]
+fn test_rtp() {
+    let socket = receiver();
+    for &order in [Little, Big, Native].iter() {
+        let format = SampleFormat::<i16, &str>::new(44100, 2, order, None);
+        let samples: Vec<i16> = (0..1001i16).map(|s| s.wrapping_mul(331)).collect();
+        let given: Vec<u8> = samples.iter().flat_map(|&s| match order {
+            Little => s.to_le_bytes(),
+            Big => s.to_be_bytes(),
+            Native => s.to_ne_bytes(),
+        }).collect();
+        let given: Vec<i16> = given.chunks(2).map(|b| i16::from_ne_bytes([b[0], b[1]])).collect();
+
+        let mut sink = NetworkSink::rtp("127.0.0.1:0", socket.local_addr().unwrap(), &format)
+            .expect("Failed to create socket");
+        sink.play(&given).expect("Failed to send");
+        sink.play(&given[..4]).expect("Failed to send");
+
+        let packets = receive(&socket, 3);
+        let (headers, payloads): (Vec<_>, Vec<_>) = packets.iter().map(|p| p.split_at(12)).unzip();
+        for (i, header) in headers.iter().enumerate() {
+            assert_eq!(&header[..2], [0x80, 10]);
+            assert_eq!(u16::from_be_bytes([header[2], header[3]]), i as u16);
+        }
+        let timestamps: Vec<u32> = headers.iter()
+            .map(|h| u32::from_be_bytes([h[4], h[5], h[6], h[7]]))
+            .collect();
+        assert_eq!(timestamps, [0, 347, 500]);
+        assert_eq!(payloads[..2].concat(), bytes(&samples[..1000], true));
+        assert_eq!(payloads[2], &bytes(&samples[..4], true)[..]);
+    }
+}
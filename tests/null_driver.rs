@@ -0,0 +1,528 @@
+//! Exercise the full path from initialization to playback through libao's null driver.
+//!
+//! Every test is skipped, with a message, if the null driver is unavailable. libao may only be
+//! initialized once per process, so the tests share one `AO`.
+
+extern crate ao;
+
+use ao::{AO, AoError, Driver, DriverOptions, Endianness, OpenOptions, SampleFormat};
+use ao::Endianness::Native;
+use ao::auto::{AutoFormatDevice, SampleBuffer};
+use std::env;
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+static LIB: OnceLock<AO> = OnceLock::new();
+
+fn null() -> Option<Driver<'static>> {
+    let driver = LIB.get_or_init(AO::init).get_driver("null");
+    if driver.is_none() {
+        eprintln!("libao has no null driver; skipping");
+    }
+    driver
+}
+
+#[test]
+fn test_open_play_close() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+    let device = driver.open_live(&format).expect("Failed to open null device");
+    device.play(&[0; 4410 * 2]).expect("Playback failed");
+    device.play(&[0; 4410 * 2]).expect("Playback failed");
+    assert_eq!(device.position().frames, 8820);
+    assert_eq!(device.stats(), ao::DeviceStats { bytes: 8820 * 4, frames: 8820, writes: 2, failures: 0 });
+
+    // An interrupted write is retried.
+    #[cfg(feature = "mock")]
+    {
+        ao::mock::interrupt_next_play();
+        device.play(&[0; 2]).expect("Interrupted write not retried");
+        assert_eq!((device.stats().writes, device.stats().failures), (4, 1));
+
+        // A write refused by a busy device is retried after a pause.
+        ao::mock::refuse_next_play();
+        let start = std::time::Instant::now();
+        device.play(&[0; 2]).expect("Refused write not retried");
+        assert!(start.elapsed() >= std::time::Duration::from_millis(1));
+        assert_eq!((device.stats().writes, device.stats().failures), (6, 2));
+    }
+}
+
+#[test]
+fn test_frame_device() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<i16, &str>::new(44100, 3, Native, None);
+    let open = || driver.open_live(&format).expect("Failed to open null device");
+
+    assert_eq!(ao::FrameDevice::<i16, 2>::new(open()).err(), Some(AoError::BadFormat));
+    let device = ao::FrameDevice::<i16, 3>::new(open()).expect("Channel count rejected");
+    device.play_frames(&[[0; 3]; 100]).expect("Playback failed");
+    assert_eq!(device.position().frames, 100);
+}
+
+#[test]
+fn test_open_live_timeout() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+    let timeout = std::time::Duration::from_secs(10);
+    let device = driver.open_live_timeout(&format, &DriverOptions::new(), timeout)
+                       .expect("Failed to open null device");
+    device.play(&[0; 4410 * 2]).expect("Playback failed");
+
+    let bogus = DriverOptions::new().set("no-such-option", "1");
+    assert!(driver.open_live_timeout(&format, &bogus, timeout).is_err());
+}
+
+/// Raw input is played in whole frames, and a partial frame at the end is an error.
+#[test]
+fn test_play_from_reader() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+    let device = driver.open_live(&format).expect("Failed to open null device");
+
+    let mut calls = Vec::new();
+    let played = device.play_from_reader(&[0u8; 1000][..], 400, false, |f| calls.push(f));
+    assert_eq!(played.expect("Playback failed"), 250);
+    assert_eq!(calls, [100, 200, 250]);
+
+    let error = device.play_from_reader(&[0u8; 10][..], 400, true, |_| {}).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(device.position().frames, 252);
+}
+
+#[test]
+fn test_play_vectored() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    const RATE: usize = 12345;
+    let format = SampleFormat::<i16, &str>::new(RATE, 2, Native, None);
+    let device = driver.open_live(&format).expect("Failed to open null device");
+    device.play_vectored(&[&[1, 2, 3], &[], &[4, 5, 6, 7, 8]]).expect("Playback failed");
+    assert_eq!(device.position().frames, 4);
+    assert_eq!(device.stats().writes, 1);
+
+    #[cfg(feature = "mock")]
+    {
+        let played = ao::mock::devices().into_iter().find(|d| d.sample_rate == RATE).unwrap().played;
+        let expected: Vec<u8> = (1..9i16).flat_map(|s| s.to_ne_bytes()).collect();
+        assert_eq!(played, expected);
+    }
+}
+
+/// Playback fades in after opening and out when the device is dropped.
+#[test]
+fn test_declick() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    const RATE: usize = 1000;
+    let format = SampleFormat::<i16, &str>::new(RATE, 1, Native, None);
+    let mut device = driver.open_live(&format).expect("Failed to open null device");
+    device.set_declick(std::time::Duration::from_millis(4));
+    device.play(&[1000; 3]).expect("Playback failed");
+    device.play(&[1000; 3]).expect("Playback failed");
+    drop(device);
+
+    #[cfg(feature = "mock")]
+    {
+        let played = ao::mock::devices().into_iter().find(|d| d.sample_rate == RATE).unwrap().played;
+        let expected: Vec<u8> = [0i16, 250, 500, 750, 1000, 1000, 750, 500, 250, 0].iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+        assert_eq!(played, expected);
+    }
+
+    // Fades are applied to the values samples hold in the device's byte order.
+    const BIG_RATE: usize = 1001;
+    let format = SampleFormat::<i16, &str>::new(BIG_RATE, 1, Endianness::Big, None);
+    let mut device = driver.open_live(&format).expect("Failed to open null device");
+    device.set_declick(std::time::Duration::from_millis(4));
+    device.play(&[1000i16.to_be(); 6]).expect("Playback failed");
+    drop(device);
+
+    #[cfg(feature = "mock")]
+    {
+        let played = ao::mock::devices().into_iter().find(|d| d.sample_rate == BIG_RATE).unwrap().played;
+        let expected: Vec<u8> = [0i16, 250, 500, 750, 1000, 1000, 750, 500, 250, 0].iter()
+            .flat_map(|s| s.to_be_bytes())
+            .collect();
+        assert_eq!(played, expected);
+    }
+}
+
+/// Volume is applied to the values samples hold in the device's byte order, not this machine's.
+#[test]
+fn test_volume_byte_order() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    const RATE: usize = 13003;
+    let format = SampleFormat::<i16, &str>::new(RATE, 1, Endianness::Big, None);
+    let mut device = driver.open_live(&format).expect("Failed to open null device");
+    device.set_volume(0.5);
+    device.play(&[1000i16.to_be(), (-2i16).to_be()]).expect("Playback failed");
+
+    #[cfg(feature = "mock")]
+    {
+        let played = ao::mock::devices().into_iter().find(|d| d.sample_rate == RATE).unwrap().played;
+        assert_eq!(played, [0x01, 0xf4, 0xff, 0xff]);
+    }
+}
+
+/// Cue callbacks run, in order, after the write that reaches their frame.
+#[test]
+fn test_at_frame() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<[i16; 2], &str>::new(44100, 2, Native, None);
+    let mut device = driver.open_live(&format).expect("Failed to open null device");
+    let (sender, fired) = std::sync::mpsc::channel();
+    for &frame in [6, 4, 4, 20].iter() {
+        let sender = sender.clone();
+        device.at_frame(frame, move || sender.send(frame).unwrap());
+    }
+
+    device.play(&[[0; 2]; 3]).expect("Playback failed");
+    assert!(fired.try_recv().is_err(), "Cue fired early");
+    device.play(&[[0; 2]; 3]).expect("Playback failed");
+    assert_eq!(fired.try_iter().collect::<Vec<_>>(), vec![4, 4, 6]);
+    drop(device);
+    assert!(fired.try_recv().is_err(), "Cue past the end fired");
+}
+
+/// A tap holds the most recent frames played, and can be read from another thread.
+#[test]
+fn test_tap() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<[i16; 2], &str>::new(44100, 2, Native, None);
+    let mut device = driver.open_live(&format).expect("Failed to open null device");
+    let tap = device.tap(4);
+    assert!(tap.snapshot().is_empty());
+
+    device.play(&[[1, -1], [2, -2], [3, -3]]).expect("Playback failed");
+    assert_eq!(tap.snapshot(), vec![[1, -1], [2, -2], [3, -3]]);
+    device.set_volume(2.0);
+    device.play(&[[4, -4], [5, -5], [6, -6]]).expect("Playback failed");
+
+    let reader = tap.clone();
+    let snapshot = std::thread::spawn(move || reader.snapshot()).join().unwrap();
+    assert_eq!(snapshot, vec![[3, -3], [8, -8], [10, -10], [12, -12]]);
+    assert_eq!(tap.frames_played(), 6);
+}
+
+/// OpenOptions falls back through its drivers, and picks a file driver by extension.
+#[test]
+fn test_open_options() {
+    if null().is_none() {
+        return;
+    }
+    let lib = LIB.get_or_init(AO::init);
+    let format = || SampleFormat::<i16, &str>::new(11025, 1, Native, None);
+
+    let device = OpenOptions::new(lib, format()).drivers(vec!["no-such-driver", "null"]).open();
+    device.expect("Fallback driver not opened").play(&[0; 4]).expect("Playback failed");
+    let missing = OpenOptions::new(lib, format()).driver("no-such-driver").open();
+    assert_eq!(missing.err(), Some(AoError::NoDriver));
+    let not_live = OpenOptions::new(lib, format()).driver("null").file("unused.wav", true).open();
+    assert_eq!(not_live.err().map(|e| e.kind().clone()), Some(AoError::NotFile));
+
+    if lib.get_driver("wav").is_none() {
+        return;
+    }
+    let path = env::temp_dir().join(format!("ao-open-options-{}.wav", std::process::id()));
+    let device = OpenOptions::new(lib, format()).file(&path, true).open();
+    device.expect("File not opened").play(&[0; 4]).expect("Playback failed");
+    std::fs::remove_file(&path).expect("File not written");
+}
+
+fn names<'a, I: Iterator<Item = Driver<'a>>>(drivers: I) -> Vec<&'a str> {
+    drivers.map(|d| d.get_info().unwrap().short_name).collect()
+}
+
+#[test]
+fn test_driver_info() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let info = driver.get_info().expect("Null driver has no information");
+    assert_eq!(info.short_name, "null");
+    assert_eq!(info.flavor, ao::DriverType::Live);
+    assert!(LIB.get().unwrap().drivers().any(|d| d.get_info().map(|i| i.short_name) == Some("null")));
+
+    let lib = LIB.get().unwrap();
+    assert!(names(lib.live_drivers()).contains(&"null"));
+    assert!(!names(lib.file_drivers()).contains(&"null"));
+    if lib.get_driver("wav").is_some() {
+        assert!(names(lib.file_drivers()).contains(&"wav"));
+    }
+    let default = lib.find_live_default().expect("No live driver found");
+    assert_eq!(default.get_info().unwrap().flavor, ao::DriverType::Live);
+}
+
+#[test]
+fn test_options() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+
+    let quiet = DriverOptions::new().quiet(true);
+    driver.open_live_with(&format, &quiet).expect("Null driver rejected a common option");
+
+    let bogus = DriverOptions::new().set("no-such-option", "1");
+    assert_eq!(driver.open_live_with(&format, &bogus).err(),
+               Some(AoError::InvalidOption("no-such-option".to_string(), "1".to_string())));
+
+    // Strings libao cannot be given are reported as such, not as rejected values.
+    let nul = DriverOptions::new().set("matrix", "L,R\0");
+    assert_eq!(driver.open_live_with(&format, &nul).err(),
+               Some(AoError::InvalidString("L,R\0".to_string())));
+
+    // A path that is not UTF-8 fails as validation says it will, before any driver sees it.
+    #[cfg(unix)]
+    {
+        use ao::options::{DriverConfig, OssOptions};
+        use std::os::unix::ffi::OsStrExt;
+
+        let oss = OssOptions::new(std::ffi::OsStr::from_bytes(b"/dev/dsp\xff"));
+        let expected = AoError::InvalidOption("dsp".to_string(), "/dev/dsp\u{fffd}".to_string());
+        assert_eq!(oss.validate(&format), Err(expected.clone()));
+        assert_eq!(driver.open_live_with(&format, &oss.to_options()).err(), Some(expected));
+    }
+}
+
+/// Probing opens and closes a device for every width and channel count it tries.
+#[test]
+fn test_probe_capabilities() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    const RATE: usize = 13002;
+    let caps = driver.probe_capabilities(RATE);
+    assert!(caps.bits.contains(&16), "{:?}", caps);
+
+    #[cfg(feature = "mock")]
+    {
+        assert_eq!(caps.bits, [8, 16, 24, 32]);
+        let opened: Vec<_> = ao::mock::devices().into_iter().filter(|d| d.sample_rate == RATE).collect();
+        assert_eq!(opened.len(), 12);
+        assert!(opened.iter().all(|d| d.closed));
+        assert_eq!(opened.iter().map(|d| d.bits).take(4).collect::<Vec<_>>(), [8, 16, 24, 32]);
+    }
+}
+
+#[test]
+fn test_error_paths() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let lib = LIB.get().unwrap();
+    let format = SampleFormat::<i16, &str>::new(44100, 2, Native, None);
+
+    assert!(lib.get_driver("no-such-driver").is_none());
+    assert_eq!(lib.find_driver("null\0").err(), Some(AoError::InvalidString("null\0".to_string())));
+    assert_eq!(driver.open_file(&format, Path::new("null-output"), true).err(),
+               Some(AoError::NotFile));
+    if let Some(wav) = lib.get_driver("wav") {
+        assert_eq!(wav.open_live(&format).err(), Some(AoError::NotLive));
+
+        // libao cannot say why a file could not be opened, but the crate finds out.
+        let missing = env::temp_dir().join("ao-no-such-directory").join("out.wav");
+        let error = wav.open_file(&format, &missing, true).err().expect("Opened a missing directory");
+        assert_eq!(*error.kind(), AoError::OpenFile);
+        assert_eq!(error.os_error().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+        assert!(error.source().is_some());
+    }
+}
+
+struct Buffer {
+    width: usize,
+    channels: usize,
+    rate: usize,
+    data: &'static [u8],
+}
+
+impl SampleBuffer for Buffer {
+    fn channels(&self) -> usize { self.channels }
+    fn sample_rate(&self) -> usize { self.rate }
+    fn endianness(&self) -> Endianness { Native }
+    fn sample_width(&self) -> usize { self.width }
+    fn data<'a>(&self) -> &'a [u8] { self.data }
+}
+
+/// The device is reopened when, and only when, the format of the buffers changes.
+#[test]
+fn test_auto_format_reopen() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    // Sample rate distinguishes this test's devices from any others recorded by the mock.
+    const RATE: usize = 11111;
+    static DATA: [u32; 16] = [0; 16];
+    let data = unsafe { std::slice::from_raw_parts(DATA.as_ptr() as *const u8, 64) };
+
+    let mut device = AutoFormatDevice::new(driver, vec!["", "M", "L,R"]);
+    let stereo16 = Buffer { width: 16, channels: 2, rate: RATE, data };
+    let mono8 = Buffer { width: 8, channels: 1, rate: RATE, data };
+    device.play(&stereo16).expect("Playback failed");
+    device.play(&stereo16).expect("Playback failed");
+    device.play(&mono8).expect("Playback failed");
+    // A buffer starting at an odd address is copied before it is played as 16-bit samples.
+    let unaligned = Buffer { width: 16, channels: 2, rate: RATE, data: &data[1..9] };
+    device.play(&unaligned).expect("Playback failed");
+    let odd = Buffer { width: 24, channels: 1, rate: RATE, data };
+    assert_eq!(device.play(&odd).err(), Some(AoError::BadFormat));
+
+    #[cfg(feature = "mock")]
+    {
+        let opened: Vec<_> = ao::mock::devices().into_iter().filter(|d| d.sample_rate == RATE).collect();
+        assert_eq!(opened.len(), 3);
+        assert_eq!((opened[0].bits, opened[0].channels), (16, 2));
+        assert_eq!(opened[0].matrix.as_ref().map(|m| &m[..]), Some("L,R"));
+        assert_eq!(opened[0].played.len(), 128);
+        assert!(opened[0].closed);
+        assert_eq!((opened[1].bits, opened[1].channels), (8, 1));
+        assert_eq!(opened[1].matrix.as_ref().map(|m| &m[..]), Some("M"));
+        assert_eq!((opened[2].bits, opened[2].played.len()), (16, 8));
+    }
+}
+
+/// With a file output driver, a new file is written for each format.
+#[test]
+fn test_auto_format_files() {
+    let lib = LIB.get_or_init(AO::init);
+    let wav = || lib.get_driver("wav");
+    if wav().is_none() {
+        return;
+    }
+    static DATA: [u32; 16] = [0; 16];
+    let data = unsafe { std::slice::from_raw_parts(DATA.as_ptr() as *const u8, 64) };
+    let stereo16 = Buffer { width: 16, channels: 2, rate: 8000, data };
+    let mono8 = Buffer { width: 8, channels: 1, rate: 8000, data };
+
+    let mut untemplated = AutoFormatDevice::new(wav().unwrap(), vec![""]);
+    assert_eq!(untemplated.play(&stereo16).err(), Some(AoError::NotLive));
+
+    let dir = env::temp_dir();
+    let template = dir.join(format!("ao-auto-{}-{{n}}-{{bits}}x{{channels}}.wav", std::process::id()));
+    let mut device = AutoFormatDevice::new(wav().unwrap(), vec![""])
+        .with_file_template(template.to_str().unwrap(), true);
+    device.play(&stereo16).expect("Playback failed");
+    device.play(&mono8).expect("Playback failed");
+    drop(device);
+
+    for name in ["0-16x2", "1-8x1"].iter() {
+        let path = dir.join(format!("ao-auto-{}-{}.wav", std::process::id(), name));
+        std::fs::remove_file(&path).expect("File not written");
+    }
+}
+
+/// A driver preferring the other byte order gets samples already swapped to it.
+#[cfg(feature = "mock")]
+#[test]
+fn test_auto_preferred_byte_order() {
+    const RATE: usize = 24000;
+    let lib = LIB.get_or_init(AO::init);
+    let name = if cfg!(target_endian = "big") { "wav" } else { "au" };
+    let driver = lib.get_driver(name).unwrap();
+    static DATA: [u16; 12] = [0x0102; 12];
+    let data = unsafe { std::slice::from_raw_parts(DATA.as_ptr() as *const u8, 24) };
+    let buffer = Buffer { width: 16, channels: 2, rate: RATE, data };
+
+    let template = env::temp_dir().join(format!("ao-order-{}.au", std::process::id()));
+    let mut device = AutoFormatDevice::new(driver, vec![""])
+        .with_file_template(template.to_str().unwrap(), true);
+    device.play(&buffer).expect("Playback failed");
+    drop(device);
+    std::fs::remove_file(&template).expect("File not written");
+
+    let opened = ao::mock::devices().into_iter().find(|d| d.sample_rate == RATE).unwrap();
+    assert_ne!(opened.byte_order, Native);
+    let expected: Vec<u8> = DATA.iter().flat_map(|s| s.swap_bytes().to_ne_bytes()).collect();
+    assert_eq!(opened.played, expected);
+}
+
+/// Cached devices are reused for the same format, and the least recently used is closed first.
+#[test]
+fn test_device_cache() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    let formats: Vec<SampleFormat<i16, &str>> = [8000, 16000, 32000].iter()
+        .map(|&rate| SampleFormat::new(rate, 1, Native, None))
+        .collect();
+
+    let mut cache = ao::DeviceCache::new(2);
+    cache.get(&driver, &formats[0]).unwrap().play(&[0; 8]).unwrap();
+    cache.get(&driver, &formats[1]).unwrap();
+    assert_eq!(cache.get(&driver, &formats[0]).unwrap().position().frames, 8);
+
+    cache.get(&driver, &formats[2]).unwrap();
+    assert_eq!(cache.len(), 2);
+    assert!(cache.contains(&driver, &formats[0]));
+    assert!(!cache.contains(&driver, &formats[1]));
+
+    cache.close_all();
+    assert!(cache.is_empty());
+}
+
+/// Each device added to a `MultiDevice` receives only its own channels.
+#[test]
+fn test_multi_device() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    const FRONT: usize = 13000;
+    const REAR: usize = 13001;
+    let stereo = |rate| SampleFormat::<i16, &str>::new(rate, 2, Native, None);
+    let mono = SampleFormat::<i16, &str>::new(REAR, 1, Native, None);
+
+    let mut multi = ao::multi::MultiDevice::new(3);
+    let open = |format: &SampleFormat<i16, &str>| driver.open_live(format).expect("Failed to open null device");
+    assert_eq!(multi.add(open(&mono), &[0, 1]).err(), Some(AoError::BadFormat));
+    multi.add(open(&stereo(FRONT)), &[0, 1]).expect("Channel count rejected");
+    multi.add(open(&stereo(REAR)), &[2, 2]).expect("Channel count rejected");
+    multi.play(&[1, 2, 3, 4, 5, 6, 7]).expect("Playback failed");
+    assert_eq!(multi.device(1).unwrap().position().frames, 2);
+
+    #[cfg(feature = "mock")]
+    {
+        let played = |rate| ao::mock::devices().into_iter()
+            .find(|d| d.sample_rate == rate && d.channels == 2)
+            .unwrap()
+            .played;
+        let bytes = |samples: &[i16]| samples.iter().flat_map(|s| s.to_ne_bytes()).collect::<Vec<u8>>();
+        assert_eq!(played(FRONT), bytes(&[1, 2, 4, 5]));
+        assert_eq!(played(REAR), bytes(&[3, 3, 6, 6]));
+    }
+}
@@ -0,0 +1,483 @@
+//! Drive the background playback engine through libao's null driver.
+//!
+//! Every test is skipped, with a message, if the null driver is unavailable. libao may only be
+//! initialized once per process, so the tests share one `AO`.
+
+extern crate ao;
+
+use ao::{AO, AoError, Driver, DriverOptions, Endianness, SampleFormat};
+use ao::Endianness::Native;
+use ao::player::{PlaybackObserver, PlayerHandle, PlayerOptions, ReplayGain, Status};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static LIB: OnceLock<AO> = OnceLock::new();
+
+fn null() -> Option<Driver<'static>> {
+    let driver = LIB.get_or_init(AO::init).get_driver("null");
+    if driver.is_none() {
+        eprintln!("libao has no null driver; skipping");
+    }
+    driver
+}
+
+fn format(rate: usize) -> SampleFormat<i16, &'static str> {
+    SampleFormat::new(rate, 1, Native, None)
+}
+
+fn spawn(rate: usize, options: PlayerOptions) -> PlayerHandle<i16> {
+    PlayerHandle::spawn_with(null().unwrap(), format(rate), options).expect("Failed to open null device")
+}
+
+/// Options that do not wait for the device's latency when draining.
+fn quick() -> PlayerOptions {
+    PlayerOptions {
+        latency: Duration::from_millis(0),
+        ..PlayerOptions::default()
+    }
+}
+
+/// Frames of audio waiting in the player's queue.
+fn buffered(player: &PlayerHandle<i16>, rate: usize) -> usize {
+    (player.buffered().as_secs_f64() * rate as f64).round() as usize
+}
+
+/// Wait up to five seconds for `condition` to hold.
+fn wait_for<F: FnMut() -> bool>(mut condition: F) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "Timed out");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Every byte played at `rate`, across however many devices were opened.
+#[cfg(feature = "mock")]
+fn played(rate: usize) -> Vec<u8> {
+    ao::mock::devices().into_iter()
+        .filter(|d| d.sample_rate == rate)
+        .flat_map(|d| d.played)
+        .collect()
+}
+
+fn bytes(runs: &[(i16, usize)]) -> Vec<u8> {
+    runs.iter()
+        .flat_map(|&(s, n)| std::iter::repeat_n(s, n))
+        .flat_map(|s| s.to_ne_bytes())
+        .collect()
+}
+
+/// Playback waits for the prebuffer to fill, but starts early rather than wait forever.
+#[test]
+fn test_prebuffer() {
+    if null().is_none() {
+        return;
+    }
+    let options = || PlayerOptions {
+        prebuffer: Some(Duration::from_secs(1)),
+        ..PlayerOptions::default()
+    };
+    let spawn = |options| spawn(1000, options);
+
+    // Draining plays what there is.
+    let player = spawn(options());
+    player.enqueue(vec![0; 100]);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(player.status(), Status::Buffering);
+    assert!(player.drain(Duration::from_secs(5)), "Prebuffer never started");
+    wait_for(|| player.status() == Status::Idle);
+
+    // Filling the prebuffer starts playback.
+    let player = spawn(options());
+    player.enqueue(vec![0; 600]);
+    player.enqueue(vec![0; 600]);
+    assert!(player.drain(Duration::from_secs(5)));
+
+    // A queue bounded below the prebuffer starts once it is full, instead of blocking forever.
+    let player = spawn(PlayerOptions {
+        max_buffered: Some(Duration::from_millis(500)),
+        ..options()
+    });
+    for _ in 0..4 {
+        player.enqueue(vec![0; 400]);
+    }
+    assert!(player.drain(Duration::from_secs(5)));
+
+    // So does a flush, and closing every sender.
+    let player = spawn(options());
+    player.enqueue(vec![0; 100]);
+    player.flush();
+    player.enqueue(vec![0; 100]);
+    let sender = player.sender();
+    sender.send(vec![0; 100]).unwrap();
+    drop(sender);
+    wait_for(|| player.status() == Status::Idle);
+}
+
+/// A bounded queue refuses or blocks on samples that do not fit, unless it is empty.
+#[test]
+fn test_enqueue_backpressure() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1001;
+    let player = spawn(RATE, PlayerOptions {
+        max_buffered: Some(Duration::from_millis(100)),
+        ..quick()
+    });
+    player.pause();
+    player.try_enqueue(vec![1; 60]).expect("Samples refused");
+    assert_eq!(player.try_enqueue(vec![2; 60]), Err(vec![2; 60]));
+    assert_eq!(buffered(&player, RATE), 60);
+
+    thread::scope(|scope| {
+        let blocked = scope.spawn(|| player.enqueue(vec![3; 60]));
+        thread::sleep(Duration::from_millis(50));
+        assert!(!blocked.is_finished(), "Enqueue did not block");
+        player.resume();
+    });
+    assert!(player.drain(Duration::from_secs(5)));
+    player.try_enqueue(vec![4; 500]).expect("Empty queue refused samples");
+    assert!(player.drain(Duration::from_secs(5)));
+
+    #[cfg(feature = "mock")]
+    assert_eq!(played(RATE), bytes(&[(1, 60), (3, 60), (4, 500)]));
+}
+
+/// Nothing is played while paused, and the queue is kept for when playback resumes.
+#[test]
+fn test_pause_resume() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1002;
+    let player = spawn(RATE, quick());
+    player.pause();
+    player.enqueue(vec![1; 100]);
+    wait_for(|| player.status() == Status::Paused);
+    assert!(!player.drain(Duration::from_millis(50)), "Paused engine drained");
+    assert_eq!(buffered(&player, RATE), 100);
+
+    #[cfg(feature = "mock")]
+    assert_eq!(played(RATE), []);
+
+    player.resume();
+    assert!(player.drain(Duration::from_secs(5)));
+    wait_for(|| player.status() == Status::Idle);
+
+    #[cfg(feature = "mock")]
+    assert_eq!(played(RATE), bytes(&[(1, 100)]));
+}
+
+/// Tracks in the player's format play back to back on the same device, and report their end.
+#[test]
+fn test_tracks() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1003;
+    let player = spawn(RATE, quick());
+    let (ended, ids) = mpsc::channel();
+    player.on_track_end(move |id| ended.send(id).unwrap());
+
+    let first = player.enqueue_track(vec![vec![1; 100], vec![2; 100]], format(RATE));
+    let second = player.enqueue_track(vec![vec![3; 100]], format(RATE));
+    // A peak of 2 halves the gain to keep it from clipping.
+    let third = player.enqueue_track_with_gain(vec![vec![1000; 100]], format(RATE),
+                                               ReplayGain::new(0.0, Some(2.0)));
+    assert!(player.drain(Duration::from_secs(5)));
+    assert_eq!(ids.iter().take(3).collect::<Vec<_>>(), [first, second, third]);
+
+    #[cfg(feature = "mock")]
+    {
+        assert_eq!(ao::mock::devices().iter().filter(|d| d.sample_rate == RATE).count(), 1);
+        assert_eq!(played(RATE), bytes(&[(1, 100), (2, 100), (3, 100), (500, 100)]));
+    }
+}
+
+/// Gain is applied to the values samples hold in the device's byte order.
+#[test]
+fn test_gain_byte_order() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1020;
+    let big = SampleFormat::<i16, &'static str>::new(RATE, 1, Endianness::Big, None);
+    let player = PlayerHandle::spawn_with(null().unwrap(), big.clone(), quick())
+        .expect("Failed to open null device");
+    player.enqueue_track_with_gain(vec![vec![1000i16.to_be(); 10]], big,
+                                   ReplayGain::new(0.0, Some(2.0)));
+    assert!(player.drain(Duration::from_secs(5)));
+
+    #[cfg(feature = "mock")]
+    {
+        let expected: Vec<u8> = std::iter::repeat_n(500i16, 10).flat_map(|s| s.to_be_bytes()).collect();
+        assert_eq!(played(RATE), expected);
+    }
+}
+
+/// Silence keeps the device fed while idle or paused, if asked for.
+#[cfg(feature = "mock")]
+#[test]
+fn test_keep_alive() {
+    if null().is_none() {
+        return;
+    }
+    const IDLE: usize = 1004;
+    let player = spawn(IDLE, PlayerOptions {
+        silence_while_idle: true,
+        ..quick()
+    });
+    wait_for(|| played(IDLE).len() > 100);
+    player.pause();
+    player.enqueue(vec![1; 10]);
+    thread::sleep(Duration::from_millis(20));
+    let paused = played(IDLE).len();
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(played(IDLE).len(), paused, "Silence written while paused");
+    assert!(played(IDLE).iter().all(|&b| b == 0));
+    drop(player);
+
+    const PAUSED: usize = 1005;
+    let player = spawn(PAUSED, PlayerOptions {
+        silence_while_paused: true,
+        ..quick()
+    });
+    player.enqueue(vec![1; 10]);
+    assert!(player.drain(Duration::from_secs(5)));
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(played(PAUSED), bytes(&[(1, 10)]), "Silence written while idle");
+    player.pause();
+    wait_for(|| played(PAUSED).len() > 100);
+}
+
+/// Sends a line for every notification.
+struct Recorder(Sender<String>);
+
+impl PlaybackObserver for Recorder {
+    fn started(&mut self) {
+        self.0.send("started".to_string()).unwrap();
+    }
+    fn progress(&mut self, frames: u64) {
+        self.0.send(format!("progress {}", frames)).unwrap();
+    }
+    fn format_changed(&mut self, sample_rate: usize, channels: usize) {
+        self.0.send(format!("format {} {}", sample_rate, channels)).unwrap();
+    }
+    fn underrun(&mut self) {
+        self.0.send("underrun".to_string()).unwrap();
+    }
+    fn ended(&mut self) {
+        self.0.send("ended".to_string()).unwrap();
+    }
+}
+
+/// Observers hear about playback starting and starving, progress, format changes and the end.
+#[test]
+fn test_observers() {
+    if null().is_none() {
+        return;
+    }
+    let player = spawn(1006, PlayerOptions {
+        progress_interval: Some(100),
+        ..quick()
+    });
+    let (sender, notifications) = mpsc::channel();
+    player.add_observer(Recorder(sender));
+
+    player.enqueue(vec![0; 250]);
+    assert!(player.drain(Duration::from_secs(5)));
+    player.enqueue_track(vec![vec![0; 200]], SampleFormat::<i16, &str>::new(1007, 2, Native, None));
+    assert!(player.drain(Duration::from_secs(5)));
+    drop(player);
+
+    assert_eq!(notifications.iter().collect::<Vec<_>>(),
+               ["started", "progress 250", "underrun", "started", "format 1007 2", "progress 350",
+                "underrun", "ended"]);
+}
+
+/// Flushing discards the queue without ending tracks, and draining then has nothing to wait for.
+#[test]
+fn test_drain_flush() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1008;
+    let player = spawn(RATE, quick());
+    let (ended, ids) = mpsc::channel();
+    player.on_track_end(move |id| ended.send(id).unwrap());
+
+    player.pause();
+    player.enqueue(vec![1; 100]);
+    player.enqueue_track(vec![vec![2; 100]], format(RATE));
+    player.flush();
+    assert!(player.drain(Duration::from_secs(5)), "Flushed queue not drained");
+    assert_eq!(player.buffered(), Duration::from_millis(0));
+
+    player.resume();
+    player.enqueue(vec![3; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+    assert_eq!(ids.try_recv().ok(), None);
+
+    // Draining a stopped engine returns at once.
+    player.stop();
+    wait_for(|| player.status() == Status::Stopped);
+    player.enqueue(vec![4; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+
+    #[cfg(feature = "mock")]
+    assert_eq!(played(RATE), bytes(&[(3, 100)]));
+}
+
+/// Buffers sent down a channel are played in order.
+#[test]
+fn test_sender() {
+    let driver = match null() {
+        Some(d) => d,
+        None => return,
+    };
+    const RATE: usize = 1009;
+    let (player, sender) = PlayerHandle::spawn_channel(driver, format(RATE)).unwrap();
+    let second = player.sender();
+    sender.send(vec![1; 100]).unwrap();
+    second.send(vec![2; 100]).unwrap();
+    sender.send(vec![3; 100]).unwrap();
+    drop((sender, second));
+
+    // Each channel keeps its own order, but the two may interleave.
+    #[cfg(feature = "mock")]
+    {
+        wait_for(|| played(RATE).len() == 600);
+        let played = played(RATE);
+        let (second, first): (Vec<_>, Vec<_>) = played.chunks(2).partition(|&s| s == &bytes(&[(2, 1)])[..]);
+        assert_eq!(second.len(), 100);
+        assert_eq!(first.concat(), bytes(&[(1, 100), (3, 100)]));
+    }
+    drop(player);
+}
+
+/// Prebuffering ends once every sender has been dropped, not when the first is.
+#[test]
+fn test_senders_prebuffer() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1014;
+    let player = spawn(RATE, PlayerOptions {
+        prebuffer: Some(Duration::from_secs(1)),
+        ..quick()
+    });
+    let (first, second) = (player.sender(), player.sender());
+    first.send(vec![1; 100]).unwrap();
+    second.send(vec![2; 100]).unwrap();
+    wait_for(|| buffered(&player, RATE) == 200);
+
+    drop(first);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(buffered(&player, RATE), 200, "Prebuffering ended with a sender left");
+    drop(second);
+    wait_for(|| buffered(&player, RATE) == 0);
+}
+
+/// A scheduled track is preceded by silence lasting until its start time.
+#[test]
+fn test_play_at() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 1010;
+    let player = spawn(RATE, quick());
+    player.play_at(Instant::now() + Duration::from_millis(200), vec![vec![5; 50]], format(RATE));
+    assert!(player.drain(Duration::from_secs(5)));
+
+    #[cfg(feature = "mock")]
+    {
+        let played = played(RATE);
+        let lead = played.len() / 2 - 50;
+        assert!(lead > RATE * 3 / 20 && lead <= RATE / 5, "{} frames of silence", lead);
+        assert!(played[..lead * 2].iter().all(|&b| b == 0));
+        assert_eq!(played[lead * 2..], bytes(&[(5, 50)])[..]);
+    }
+}
+
+/// A track played for a duration stops there, however long its source.
+#[test]
+fn test_play_for() {
+    if null().is_none() {
+        return;
+    }
+    const RATE: usize = 2000;
+    let player = spawn(RATE, quick());
+    player.play_for(std::iter::repeat(vec![7; 300]), format(RATE), Duration::from_millis(500));
+    assert!(player.drain(Duration::from_secs(5)), "Track never ended");
+
+    #[cfg(feature = "mock")]
+    assert_eq!(played(RATE), bytes(&[(7, 1000)]));
+}
+
+/// Cues run once the chunk crossing their frame has been written.
+#[test]
+fn test_at_frame() {
+    if null().is_none() {
+        return;
+    }
+    let player = spawn(1012, quick());
+    let (sender, cued) = mpsc::channel();
+    let cue = |frame: u64| {
+        let sender = sender.clone();
+        move || sender.send(frame).unwrap()
+    };
+    player.at_frame(250, cue(250));
+    player.at_frame(50, cue(50));
+    player.enqueue(vec![0; 100]);
+    player.enqueue(vec![0; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+    assert_eq!(cued.try_iter().collect::<Vec<_>>(), [50]);
+
+    // A cue for a frame already played runs along with the next chunk's.
+    player.at_frame(10, cue(10));
+    player.enqueue(vec![0; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+    assert_eq!(cued.try_iter().collect::<Vec<_>>(), [10, 250]);
+}
+
+/// Playback moves to another driver or a file, and stays put if the new device will not open.
+#[test]
+fn test_switch_driver() {
+    if null().is_none() {
+        return;
+    }
+    let lib = LIB.get().unwrap();
+    let raw = match lib.get_driver("raw") {
+        Some(d) => d,
+        None => return,
+    };
+    const RATE: usize = 1013;
+    let player = spawn(RATE, quick());
+    assert_eq!(player.switch_driver(lib.get_driver("raw").unwrap(), DriverOptions::new()),
+               Err(AoError::NotLive));
+    player.enqueue(vec![1; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+
+    let path = std::env::temp_dir().join(format!("ao-player-{}.raw", std::process::id()));
+    player.switch_to_file(raw, &path, true, DriverOptions::new()).expect("Switch failed");
+    player.enqueue(vec![2; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+    player.switch_driver(null().unwrap(), DriverOptions::new()).expect("Switch failed");
+    player.enqueue(vec![3; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+    let written = std::fs::read(&path).expect("File not written");
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(written, bytes(&[(2, 100)]));
+
+    #[cfg(feature = "mock")]
+    assert_eq!(played(RATE), bytes(&[(1, 100), (2, 100), (3, 100)]));
+
+    player.stop();
+    wait_for(|| player.status().is_stopped());
+    assert_eq!(player.switch_driver(null().unwrap(), DriverOptions::new()),
+               Err(AoError::OpenDevice));
+}
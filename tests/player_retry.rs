@@ -0,0 +1,103 @@
+//! The playback engine reopens its device when opening or writing fails.
+//!
+//! Failures are injected into whichever device next opens or plays, so this runs in a process of
+//! its own.
+#![cfg(feature = "mock")]
+
+extern crate ao;
+
+use ao::{AO, AoError, DriverOptions, SampleFormat};
+use ao::Endianness::Native;
+use ao::player::{Event, PlayerHandle, PlayerOptions, RetryPolicy, Status};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_player_retry() {
+    let lib: &'static AO = Box::leak(Box::new(AO::init()));
+    let format = || SampleFormat::<i16, &str>::new(2000, 1, Native, None);
+    let retry = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        ..RetryPolicy::default()
+    };
+
+    // Opening is retried when the player is spawned.
+    ao::mock::fail_next_open(AoError::OpenDevice);
+    let player = PlayerHandle::spawn_with(lib.get_driver("null").unwrap(), format(), PlayerOptions {
+        retry,
+        latency: Duration::from_millis(0),
+        ..PlayerOptions::default()
+    }).expect("Open not retried");
+    let (sender, events) = mpsc::channel();
+    player.on_event(move |e| sender.send(e.clone()).unwrap());
+
+    // A failed write is repeated on a new device.
+    ao::mock::fail_next_play();
+    player.enqueue(vec![1; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+    let events: Vec<Event> = events.try_iter().collect();
+    assert!(matches!(events[..], [Event::PlayFailed(_), Event::Reopened(1)]), "{:?}", events);
+    // The driver's errno is reported along with the failure.
+    if let Event::PlayFailed(ref e) = events[0] {
+        assert_eq!(e.kind(), &AoError::Play);
+        assert!(e.os_error().is_some(), "{:?}", e);
+    }
+    let opened = ao::mock::devices();
+    assert_eq!(opened.len(), 2);
+    assert!(opened[0].closed);
+    let expected: Vec<u8> = [1i16; 100].iter().flat_map(|s| s.to_ne_bytes()).collect();
+    assert_eq!(opened[1].played, expected);
+    drop(player);
+
+    // Without retries, the engine gives up.
+    let player = PlayerHandle::spawn_with(lib.get_driver("null").unwrap(), format(), PlayerOptions {
+        retry: RetryPolicy::none(),
+        ..PlayerOptions::default()
+    }).unwrap();
+    ao::mock::fail_next_play();
+    player.enqueue(vec![1; 100]);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !matches!(player.status(), Status::Failed(_)) {
+        assert!(Instant::now() < deadline, "Engine did not fail");
+        thread::sleep(Duration::from_millis(5));
+    }
+    drop(player);
+
+    // A file is finished rather than started over when it cannot be written to.
+    let path = std::env::temp_dir().join(format!("ao-player-retry-{}.wav", std::process::id()));
+    let player = PlayerHandle::spawn_with(lib.get_driver("null").unwrap(), format(), PlayerOptions {
+        retry,
+        latency: Duration::from_millis(0),
+        ..PlayerOptions::default()
+    }).unwrap();
+    let (sender, events) = mpsc::channel();
+    player.on_event(move |e| sender.send(e.clone()).unwrap());
+    let wav = lib.get_driver("wav").unwrap();
+    player.switch_to_file(wav, &path, true, DriverOptions::new()).expect("Switch failed");
+    player.enqueue(vec![1; 100]);
+    // A track in another format is skipped, leaving the file open.
+    player.enqueue_track(vec![vec![2; 100]], SampleFormat::<i16, &str>::new(4000, 1, Native, None));
+    player.enqueue(vec![3; 100]);
+    assert!(player.drain(Duration::from_secs(5)));
+
+    ao::mock::fail_next_play();
+    player.enqueue(vec![4; 100]);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !matches!(player.status(), Status::Failed(_)) {
+        assert!(Instant::now() < deadline, "Engine did not fail");
+        thread::sleep(Duration::from_millis(5));
+    }
+    let events: Vec<Event> = events.try_iter().collect();
+    assert!(events.iter().any(|e| matches!(*e, Event::ReopenFailed(1, AoError::FileExists))),
+            "{:?}", events);
+    drop(player);
+
+    let written = std::fs::read(&path).expect("File not written");
+    std::fs::remove_file(&path).unwrap();
+    let samples: Vec<u8> = [1i16; 100].iter().chain([3i16; 100].iter())
+        .flat_map(|s| s.to_le_bytes())
+        .collect();
+    assert_eq!(&written[40..44], &400u32.to_le_bytes(), "Header not finished");
+    assert_eq!(&written[44..], &samples[..]);
+}
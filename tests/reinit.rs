@@ -0,0 +1,34 @@
+//! libao may be initialized again once the `AO` is dropped.
+//!
+//! This creates and drops its own `AO`, so it runs in a process of its own rather than with the
+//! tests sharing one, and as a single test so nothing else holds an `AO` meanwhile.
+
+extern crate ao;
+
+use ao::AO;
+use std::thread;
+
+#[test]
+fn test_init_drop_init() {
+    let lib = AO::init();
+    assert!(AO::try_init().is_none());
+    drop(lib);
+
+    let lib = AO::init();
+    assert!(lib.drivers().count() > 0);
+    drop(lib);
+
+    // Creating an `AO` while another thread drops one never panics.
+    let threads: Vec<_> = (0..4).map(|_| thread::spawn(|| {
+        let mut created = 0;
+        for _ in 0..200 {
+            if let Some(lib) = AO::try_init() {
+                created += 1;
+                drop(lib);
+            }
+        }
+        created
+    })).collect();
+    let created: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+    assert!(created > 0);
+}
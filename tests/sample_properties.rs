@@ -0,0 +1,115 @@
+//! Properties of sample scaling that must hold for any input.
+//!
+//! Each property is checked against many pseudo-random inputs from a fixed seed, so failures are
+//! reproducible.
+
+extern crate ao;
+
+use ao::Sample;
+
+/// Cases tried for each property.
+const CASES: usize = 20_000;
+
+/// xorshift64*: quality is unimportant, reproducibility is.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn i16(&mut self) -> i16 {
+        // Favour the extremes, where saturation happens.
+        match self.next() % 8 {
+            0 => i16::MIN,
+            1 => i16::MAX,
+            _ => self.next() as i16,
+        }
+    }
+
+    /// A gain from silence to 4x amplification.
+    fn gain(&mut self) -> f32 {
+        (self.next() % 4001) as f32 / 1000.0
+    }
+
+    /// Noise in the range (-1, 1), as `Device` generates for dither.
+    fn noise(&mut self) -> f32 {
+        ((self.next() % 1999) as f32 - 999.0) / 1000.0
+    }
+}
+
+fn exact(sample: i16, gain: f32) -> f64 {
+    sample as f64 * gain as f64
+}
+
+/// The value closest to `value` that an `i16` can hold.
+fn saturate(value: f64) -> f64 {
+    value.max(i16::MIN as f64).min(i16::MAX as f64)
+}
+
+#[test]
+fn test_unity_gain_is_identity() {
+    let mut rng = Rng(1);
+    for _ in 0..CASES {
+        let s = rng.i16();
+        assert_eq!(s.scale(1.0), s);
+        assert_eq!(s.scale_dithered(1.0, &mut || 0.0), s);
+    }
+}
+
+#[test]
+fn test_scale_is_monotonic() {
+    let mut rng = Rng(2);
+    for _ in 0..CASES {
+        let (a, b, gain) = (rng.i16(), rng.i16(), rng.gain());
+        let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+        assert!(lo.scale(gain) <= hi.scale(gain), "{} and {} at gain {}", lo, hi, gain);
+    }
+}
+
+#[test]
+fn test_scale_within_one_lsb() {
+    let mut rng = Rng(3);
+    for _ in 0..CASES {
+        let (s, gain) = (rng.i16(), rng.gain());
+        let error = (s.scale(gain) as f64 - saturate(exact(s, gain))).abs();
+        assert!(error < 1.0, "{} at gain {} is off by {}", s, gain, error);
+    }
+}
+
+#[test]
+fn test_scale_saturates() {
+    let mut rng = Rng(4);
+    for _ in 0..CASES {
+        let gain = 1.0 + rng.gain();
+        assert_eq!(i16::MAX.scale(gain), i16::MAX);
+        assert_eq!(i16::MIN.scale(gain), i16::MIN);
+        assert_eq!(i8::MIN.scale(gain), i8::MIN);
+        assert_eq!(i32::MAX.scale(gain), i32::MAX);
+    }
+}
+
+/// Dither adds at most one least significant bit of error beyond rounding.
+#[test]
+fn test_dither_bounded() {
+    let mut rng = Rng(5);
+    for _ in 0..CASES {
+        let (s, gain, noise) = (rng.i16(), rng.gain(), rng.noise());
+        let dithered = s.scale_dithered(gain, &mut || noise);
+        let error = (dithered as f64 - saturate(exact(s, gain))).abs();
+        assert!(error <= 1.5, "{} at gain {} with noise {} is off by {}", s, gain, noise, error);
+    }
+}
+
+/// Scaling a frame scales each of its channels alike.
+#[test]
+fn test_frames_scale_per_channel() {
+    let mut rng = Rng(6);
+    for _ in 0..CASES {
+        let (l, r, gain) = (rng.i16(), rng.i16(), rng.gain());
+        assert_eq!([l, r].scale(gain), [l.scale(gain), r.scale(gain)]);
+    }
+}